@@ -1,24 +1,30 @@
-use clap::{command, ArgGroup, Parser};
+use clap::{ArgGroup, Parser, ValueEnum};
 use core::num::NonZeroUsize;
 use core::result::Result;
-use csv::{ReaderBuilder, StringRecord, WriterBuilder};
+use csv::{ReaderBuilder, StringRecord, Writer, WriterBuilder};
 use nom::{
     branch::alt,
-    bytes::complete::tag,
+    bytes::complete::{is_not, tag},
     character::complete::{char, one_of},
     combinator::recognize,
-    multi::{many0, many1, separated_list0},
+    multi::{many0, many1, separated_list0, separated_list1},
     sequence::{terminated, tuple},
     IResult,
 };
+use regex::Regex;
+use serde_json::Value;
 use std::error::Error;
 use std::fmt::Display;
-use std::fs::File;
+use std::fs::{self, File};
 use std::io;
-use std::io::{BufRead, BufReader};
+use std::io::{BufRead, BufReader, Stdout, Write};
+use std::iter;
 use std::ops::Range;
+use std::str::FromStr;
+use unicode_segmentation::UnicodeSegmentation;
 
 type PositionList = Vec<Range<usize>>;
+type FieldList = Vec<Field>;
 type CutResult<T> = Result<T, Box<dyn Error>>;
 #[derive(Debug)]
 pub enum Extract {
@@ -26,6 +32,152 @@ pub enum Extract {
     Bytes(PositionList),
     Chars(PositionList),
 }
+/// The positions to cut out of a line, parsed from the list syntax `-b`, `-c`
+/// and `-f` take, such as `1,3-5,7-`. Positions count from 1 in the syntax
+/// and are kept as zero-based ranges.
+///
+/// A parsed selection keeps the order and repeats it was given in, like
+/// `--reorder`. [`Selection::normalized`] cuts in line order like `cut` does.
+///
+/// ```
+/// use cutr::Selection;
+///
+/// let selection: Selection = "4,1-2".parse().unwrap();
+/// assert_eq!(selection.chars("cutr").collect::<String>(), "rcu");
+/// assert_eq!(selection.normalized().chars("cutr").collect::<String>(), "cur");
+/// assert!("0".parse::<Selection>().is_err());
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Selection(PositionList);
+impl Selection {
+    /// The selected zero-based ranges, open ended ones end at `usize::MAX`
+    ///
+    /// ```
+    /// let selection: cutr::Selection = "2-3,5-".parse().unwrap();
+    /// assert_eq!(selection.ranges(), &[1..3, 4..usize::MAX]);
+    /// ```
+    pub fn ranges(&self) -> &[Range<usize>] {
+        &self.0
+    }
+    /// Sorts the ranges and merges any that overlap, so nothing is selected twice
+    ///
+    /// ```
+    /// let selection: cutr::Selection = "3,1-2,2".parse().unwrap();
+    /// assert_eq!(selection.normalized().ranges(), &[0..3]);
+    /// ```
+    pub fn normalized(&self) -> Selection {
+        Selection(normalize(self.0.clone()))
+    }
+    /// Everything this selection leaves out, in line order
+    ///
+    /// ```
+    /// let selection: cutr::Selection = "2,4-".parse().unwrap();
+    /// assert_eq!(selection.complement().ranges(), &[0..1, 2..3]);
+    /// ```
+    pub fn complement(&self) -> Selection {
+        Selection(invert(self.0.clone()))
+    }
+    /// The selected bytes of `line`, a slice for every range
+    ///
+    /// ```
+    /// let selection: cutr::Selection = "1-2,5".parse().unwrap();
+    /// assert_eq!(selection.bytes(b"bytes").collect::<Vec<_>>(), [&b"by"[..], b"s"]);
+    /// ```
+    pub fn bytes<'a>(&'a self, line: &'a [u8]) -> impl Iterator<Item = &'a [u8]> + 'a {
+        self.0
+            .iter()
+            .map(move |range| &line[clamp(range, line.len())])
+    }
+    /// Like [`Selection::bytes`], but a character is only selected when all of
+    /// its bytes are, so multi-byte characters are never split
+    ///
+    /// ```
+    /// let selection: cutr::Selection = "1-3".parse().unwrap();
+    /// assert_eq!(selection.whole_chars("ábc").collect::<String>(), "áb");
+    /// let selection: cutr::Selection = "2-3".parse().unwrap();
+    /// assert_eq!(selection.whole_chars("ábc").collect::<String>(), "b");
+    /// ```
+    pub fn whole_chars<'a>(&'a self, line: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.0.iter().map(move |range| {
+            let Range { mut start, mut end } = clamp(range, line.len());
+            while !line.is_char_boundary(start) {
+                start += 1;
+            }
+            while !line.is_char_boundary(end) {
+                end -= 1;
+            }
+            if start < end {
+                &line[start..end]
+            } else {
+                ""
+            }
+        })
+    }
+    /// The selected characters of `line`, a slice for every range
+    ///
+    /// ```
+    /// let selection: cutr::Selection = "1,3-".parse().unwrap();
+    /// assert_eq!(selection.chars("ábcd").collect::<Vec<_>>(), ["á", "cd"]);
+    /// ```
+    pub fn chars<'a>(&'a self, line: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.slices(line, line.char_indices().map(|(idx, _)| idx))
+    }
+    /// The selected extended grapheme clusters of `line`, a slice for every range
+    ///
+    /// ```
+    /// let selection: cutr::Selection = "1".parse().unwrap();
+    /// assert_eq!(selection.graphemes("e\u{301}cole").collect::<String>(), "e\u{301}");
+    /// ```
+    pub fn graphemes<'a>(&'a self, line: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.slices(line, line.grapheme_indices(true).map(|(idx, _)| idx))
+    }
+    /// The selected fields of `record`
+    ///
+    /// ```
+    /// use csv::StringRecord;
+    ///
+    /// let record = StringRecord::from(vec!["Captain", "Sham", "12345"]);
+    /// let selection: cutr::Selection = "3,1".parse().unwrap();
+    /// assert_eq!(selection.fields(&record).collect::<Vec<_>>(), ["12345", "Captain"]);
+    /// ```
+    pub fn fields<'a>(&'a self, record: &'a StringRecord) -> impl Iterator<Item = &'a str> + 'a {
+        self.0
+            .iter()
+            .flat_map(move |range| clamp(range, record.len()))
+            .filter_map(move |idx| record.get(idx))
+    }
+    /// Cuts `line` into a slice per range, `starts` are the byte offsets of
+    /// the items the ranges count
+    fn slices<'a>(
+        &'a self,
+        line: &'a str,
+        starts: impl Iterator<Item = usize>,
+    ) -> impl Iterator<Item = &'a str> + 'a {
+        let bounds: Vec<usize> = starts.chain(iter::once(line.len())).collect();
+        let items = bounds.len() - 1;
+        self.0.iter().map(move |range| {
+            let range = clamp(range, items);
+            &line[bounds[range.start]..bounds[range.end]]
+        })
+    }
+}
+impl From<PositionList> for Selection {
+    fn from(ranges: PositionList) -> Self {
+        Selection(ranges)
+    }
+}
+impl FromStr for Selection {
+    type Err = String;
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        parse_position(input).map(Selection)
+    }
+}
+/// A field selected by position, or by name when `--header` is set
+#[derive(Debug, Clone, PartialEq)]
+pub enum Field {
+    Position(Range<usize>),
+    Name(String),
+}
 #[derive(Parser, Debug)]
 #[command(version, author, about = "Rust uniq")]
 #[clap(group(ArgGroup::new("filters")
@@ -33,95 +185,382 @@ pub enum Extract {
     .args(&["chars", "bytes", "fields"])
 ))]
 pub struct Config {
-    /// Input file
-    #[arg(name = "FILES", default_value = "")]
+    /// Input files, stdin when none or "-" is given
+    #[arg(name = "FILES", default_value = "-")]
     files: Vec<String>,
     /// Field delimiter
     #[arg(short, long = "delim", default_value = "\t", value_parser = parse_delimiter)]
-    delimiter: u8,
+    delimiter: String,
+    /// Split fields on matches of a regular expression instead of a delimiter
+    #[arg(long, value_name = "PATTERN", conflicts_with = "delimiter", value_parser = parse_regex)]
+    regex_delimiter: Option<Regex>,
+    /// Delimiter between output fields, the input delimiter by default
+    #[arg(long, value_name = "STRING")]
+    output_delimiter: Option<String>,
     /// Selected characters
-    #[arg(short, long, default_value = None, allow_hyphen_values(true), value_parser = parse_position)]
-    chars: Option<PositionList>,
+    #[arg(short, long, default_value = None, allow_hyphen_values(true), value_parser = Selection::from_str)]
+    chars: Option<Selection>,
     /// Selected bytes
-    #[arg(short, long, default_value = None, allow_hyphen_values(true),  value_parser = parse_position)]
-    bytes: Option<PositionList>,
-    /// Selected fields
-    #[arg(short, long, default_value = None, allow_hyphen_values(true), value_parser = parse_position)]
-    fields: Option<PositionList>,
+    #[arg(short, long, default_value = None, allow_hyphen_values(true),  value_parser = Selection::from_str)]
+    bytes: Option<Selection>,
+    /// Selected fields, by position or by name with --header
+    #[arg(short, long, default_value = None, allow_hyphen_values(true), value_parser = parse_fields)]
+    fields: Option<FieldList>,
+    /// Resolve field names against the first record of each file
+    #[arg(long, conflicts_with_all = ["chars", "bytes"])]
+    header: bool,
+    /// Output selections in the order given, repeating any selected twice
+    #[arg(long)]
+    reorder: bool,
+    /// Select everything except the given bytes, characters or fields
+    #[arg(long, conflicts_with = "reorder")]
+    complement: bool,
+    /// Split fields like cut: no quoting, lines without a delimiter are printed as is
+    #[arg(long, conflicts_with_all = ["chars", "bytes"])]
+    raw: bool,
+    /// Do not print lines without a delimiter
+    #[arg(short = 's', long, conflicts_with_all = ["chars", "bytes"])]
+    only_delimited: bool,
+    /// Count characters as extended grapheme clusters
+    #[arg(long, conflicts_with_all = ["bytes", "fields"])]
+    graphemes: bool,
+    /// Do not split multi-byte characters when selecting bytes
+    #[arg(short = 'n', conflicts_with_all = ["chars", "fields"])]
+    no_split: bool,
+    /// Split lines into fixed-width columns of these character widths
+    #[arg(
+        long,
+        value_name = "WIDTHS",
+        value_parser = parse_widths,
+        conflicts_with_all = ["chars", "bytes", "delimiter", "regex_delimiter", "raw"]
+    )]
+    widths: Option<PositionList>,
+    /// Read fixed-width columns from a file of name,start,width lines
+    #[arg(
+        long,
+        value_name = "FILE",
+        conflicts_with_all = ["chars", "bytes", "delimiter", "regex_delimiter", "raw", "widths"]
+    )]
+    spec: Option<String>,
+    /// Output format of the selected fields
+    #[arg(long, value_enum, conflicts_with_all = ["chars", "bytes", "output_delimiter"])]
+    format: Option<Format>,
 }
-pub fn run(config: Config) -> CutResult<()> {
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+enum Format {
+    Tsv,
+    Csv,
+    Json,
+    Ndjson,
+}
+pub fn run(config: Config) -> CutResult<i32> {
     let Config {
         files,
         delimiter,
+        regex_delimiter,
+        output_delimiter,
         chars,
         bytes,
         fields,
+        header,
+        reorder,
+        complement,
+        raw,
+        only_delimited,
+        graphemes,
+        no_split,
+        widths,
+        spec,
+        format,
     } = config;
-    for filename in files {
-        match open(&filename) {
-            Err(err) => eprintln!("{}: {}", filename, err),
-            Ok(file) => {
-                if let Some(byte_pos) = &bytes {
-                    for line in file.lines() {
-                        println!("{}", extract_bytes(&line?, byte_pos));
-                    }
-                } else if let Some(char_pos) = &chars {
-                    for line in file.lines() {
-                        println!("{}", extract_chars(&line?, char_pos));
-                    }
-                } else if let Some(field_pos) = &fields {
-                    let mut reader = ReaderBuilder::new()
-                        .delimiter(delimiter)
-                        .has_headers(false)
-                        .from_reader(file);
-                    let mut wtr = WriterBuilder::new()
-                        .delimiter(delimiter)
-                        .from_writer(io::stdout());
-                    for record in reader.records() {
-                        let record = record?;
-                        wtr.write_record(extract_fields(&record, field_pos))?;
-                    }
+    let arrange = |selection: Selection| {
+        if complement {
+            selection.complement()
+        } else if reorder {
+            selection
+        } else {
+            selection.normalized()
+        }
+    };
+    let bytes = bytes.map(arrange);
+    let chars = chars.map(arrange);
+    // A regex has no text to write back, so fields are separated by a space
+    let output_delimiter = match (output_delimiter, &regex_delimiter) {
+        (Some(output_delimiter), _) => output_delimiter,
+        (None, Some(_)) => " ".to_string(),
+        (None, None) => delimiter.clone(),
+    };
+    // Column names from a spec file work like a header every file shares
+    let (splitter, names) = match (widths, spec, regex_delimiter) {
+        (Some(widths), _, _) => (Splitter::Fixed(widths.into()), None),
+        (None, Some(spec), _) => {
+            let text = fs::read_to_string(&spec).map_err(|e| format!("{}: {}", spec, e))?;
+            let (names, columns) = parse_spec(&text).map_err(|e| format!("{}: {}", spec, e))?;
+            (Splitter::Fixed(columns.into()), Some(names))
+        }
+        (None, None, Some(regex)) => (Splitter::Regex(regex), None),
+        (None, None, None) => match delimiter.as_bytes() {
+            [byte] if !raw => (
+                Splitter::Csv {
+                    delimiter: *byte,
+                    flexible: only_delimited,
+                },
+                None,
+            ),
+            _ => (Splitter::Text(delimiter), None),
+        },
+    };
+    // Without a header there is nothing to resolve names against
+    let positions = match &fields {
+        Some(fields) if !header => Some(arrange(resolve_fields(fields, names.as_ref())?.into())),
+        _ => None,
+    };
+    let mut wtr = match format {
+        Some(Format::Tsv) => FieldWriter::Tsv,
+        Some(Format::Csv) => FieldWriter::new(",", true),
+        Some(Format::Json) => FieldWriter::Json { records: 0 },
+        Some(Format::Ndjson) => FieldWriter::Ndjson,
+        None => FieldWriter::new(&output_delimiter, matches!(splitter, Splitter::Csv { .. })),
+    };
+    let mut cut = |filename: &str| -> CutResult<()> {
+        let file = open(filename)?;
+        if let Some(bytes) = &bytes {
+            for line in file.lines() {
+                let line = line?;
+                if no_split {
+                    println!("{}", bytes.whole_chars(&line).collect::<String>());
+                } else {
+                    let selected = bytes.bytes(line.as_bytes()).collect::<Vec<_>>().concat();
+                    println!("{}", String::from_utf8_lossy(&selected));
+                }
+            }
+        } else if let Some(chars) = &chars {
+            for line in file.lines() {
+                let line = line?;
+                let selected: String = if graphemes {
+                    chars.graphemes(&line).collect()
                 } else {
-                    unimplemented!()
+                    chars.chars(&line).collect()
+                };
+                println!("{}", selected);
+            }
+        } else if let Some(fields) = &fields {
+            let mut records = splitter.records(file).peekable();
+            let first = match records.peek() {
+                Some(Ok(record)) if header => record.clone(),
+                _ => StringRecord::new(),
+            };
+            let names = if header { Some(&first) } else { names.as_ref() };
+            let field_pos = match &positions {
+                Some(positions) => positions.clone(),
+                None => arrange(resolve_fields(fields, names)?.into()),
+            };
+            let keys: Option<Vec<&str>> = names.map(|names| field_pos.fields(names).collect());
+            // The header names the fields of JSON objects instead of being one
+            if header && wtr.is_keyed() {
+                records.next();
+            }
+            for record in records {
+                let record = record?;
+                // A single field is a line without a delimiter
+                if record.len() == 1 && (raw || only_delimited) {
+                    if !only_delimited {
+                        wtr.write(&[&record[0]], None)?;
+                    }
+                    continue;
                 }
+                let selected: Vec<&str> = field_pos.fields(&record).collect();
+                wtr.write(&selected, keys.as_deref())?;
             }
+            wtr.flush()?;
+        } else {
+            unimplemented!()
+        }
+        Ok(())
+    };
+    let mut failed = false;
+    for filename in &files {
+        if let Err(err) = cut(filename) {
+            eprintln!("{}: {}", filename, err);
+            failed = true;
         }
     }
+    wtr.finish()?;
 
-    Ok(())
-}
-fn extract_bytes(line: &str, chars_pos: &[Range<usize>]) -> String {
-    let mut buffer = Vec::new();
-    for cp in chars_pos.iter() {
-        line.bytes().enumerate().for_each(|(idx, c)| {
-            if cp.contains(&idx) {
-                buffer.push(c)
+    Ok(if failed { 1 } else { 0 })
+}
+/// How the fields of a line are found
+enum Splitter {
+    /// A single byte delimiter goes through the csv reader, which handles quoting.
+    /// With `flexible` records may have any number of fields, so that `-s` can
+    /// drop the lines without a delimiter.
+    Csv {
+        delimiter: u8,
+        flexible: bool,
+    },
+    Text(String),
+    Regex(Regex),
+    /// Columns of characters, trimmed of the spaces padding them
+    Fixed(Selection),
+}
+impl Splitter {
+    fn records<'a>(
+        &'a self,
+        file: Box<dyn BufRead>,
+    ) -> Box<dyn Iterator<Item = CutResult<StringRecord>> + 'a> {
+        match self {
+            Splitter::Csv {
+                delimiter,
+                flexible,
+            } => {
+                let reader = ReaderBuilder::new()
+                    .delimiter(*delimiter)
+                    .flexible(*flexible)
+                    .has_headers(false)
+                    .from_reader(file);
+                Box::new(reader.into_records().map(|record| Ok(record?)))
             }
-        });
+            Splitter::Text(delimiter) => Box::new(
+                file.lines()
+                    .map(move |line| Ok(StringRecord::from_iter(line?.split(delimiter.as_str())))),
+            ),
+            Splitter::Regex(regex) => Box::new(
+                file.lines()
+                    .map(move |line| Ok(StringRecord::from_iter(regex.split(&line?)))),
+            ),
+            Splitter::Fixed(columns) => Box::new(
+                file.lines()
+                    .map(move |line| Ok(columns.chars(&line?).map(str::trim).collect())),
+            ),
+        }
     }
-    String::from_utf8_lossy(&buffer).to_string()
 }
-fn extract_chars(line: &str, chars_pos: &[Range<usize>]) -> String {
-    let mut buffer = String::new();
-    for cp in chars_pos.iter() {
-        line.chars().enumerate().for_each(|(idx, c)| {
-            if cp.contains(&idx) {
-                buffer.push(c)
+/// Writes the selected fields. Fields read as csv go back through the csv
+/// writer when the delimiter is a single byte, so fields containing it are
+/// quoted, anything else is joined as is.
+enum FieldWriter {
+    Csv(Box<Writer<Stdout>>),
+    Joined(String),
+    /// Tab separated, with tabs, line breaks and backslashes inside fields
+    /// escaped as `\t`, `\n`, `\r` and `\\`
+    Tsv,
+    /// A JSON array of records, objects when the fields have names
+    Json {
+        records: usize,
+    },
+    /// One JSON record per line
+    Ndjson,
+}
+impl FieldWriter {
+    fn new(delimiter: &str, quoted: bool) -> Self {
+        match delimiter.as_bytes() {
+            [byte] if quoted => FieldWriter::Csv(Box::new(
+                WriterBuilder::new()
+                    .delimiter(*byte)
+                    .flexible(true)
+                    .from_writer(io::stdout()),
+            )),
+            _ => FieldWriter::Joined(delimiter.to_string()),
+        }
+    }
+    fn write(&mut self, fields: &[&str], keys: Option<&[&str]>) -> CutResult<()> {
+        match self {
+            FieldWriter::Csv(wtr) => wtr.write_record(fields)?,
+            FieldWriter::Joined(delimiter) => writeln!(io::stdout(), "{}", fields.join(delimiter))?,
+            FieldWriter::Tsv => {
+                let escaped: Vec<String> = fields.iter().map(|field| escape_tsv(field)).collect();
+                writeln!(io::stdout(), "{}", escaped.join("\t"))?
+            }
+            FieldWriter::Json { records } => {
+                let mut out = io::stdout();
+                out.write_all(if *records == 0 { b"[\n" } else { b",\n" })?;
+                serde_json::to_writer(&mut out, &json_record(fields, keys))?;
+                *records += 1;
             }
-        });
+            FieldWriter::Ndjson => {
+                let mut out = io::stdout();
+                serde_json::to_writer(&mut out, &json_record(fields, keys))?;
+                out.write_all(b"\n")?;
+            }
+        }
+        Ok(())
+    }
+    /// Whether records are written with their field names
+    fn is_keyed(&self) -> bool {
+        matches!(self, FieldWriter::Json { .. } | FieldWriter::Ndjson)
+    }
+    fn flush(&mut self) -> CutResult<()> {
+        match self {
+            FieldWriter::Csv(wtr) => wtr.flush()?,
+            _ => io::stdout().flush()?,
+        }
+        Ok(())
+    }
+    /// Closes the JSON array once every file has been written
+    fn finish(&mut self) -> CutResult<()> {
+        match self {
+            FieldWriter::Json { records: 0 } => println!("[]"),
+            FieldWriter::Json { .. } => println!("\n]"),
+            _ => {}
+        }
+        self.flush()
     }
-    buffer
 }
-fn extract_fields<'a>(line: &'a StringRecord, chars_pos: &[Range<usize>]) -> Vec<&'a str> {
-    let mut buffer = Vec::new();
-    for cp in chars_pos.iter() {
-        line.into_iter().enumerate().for_each(|(idx, c)| {
-            if cp.contains(&idx) {
-                buffer.push(c)
-            }
-        });
+fn escape_tsv(field: &str) -> String {
+    let mut escaped = String::with_capacity(field.len());
+    for c in field.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\t' => escaped.push_str("\\t"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+fn json_record(fields: &[&str], keys: Option<&[&str]>) -> Value {
+    match keys {
+        Some(keys) => Value::Object(
+            keys.iter()
+                .zip(fields)
+                .map(|(key, field)| (key.to_string(), Value::from(*field)))
+                .collect(),
+        ),
+        None => Value::from(fields),
     }
-    buffer
+}
+/// Sorts the positions and merges any that overlap, so a line is cut in
+/// order and nothing is selected twice, the way cut does it.
+fn normalize(mut positions: PositionList) -> PositionList {
+    positions.sort_by_key(|range| (range.start, range.end));
+    let mut merged: PositionList = Vec::with_capacity(positions.len());
+    for range in positions {
+        match merged.last_mut() {
+            Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+            _ => merged.push(range),
+        }
+    }
+    merged
+}
+/// Everything `positions` does not select, up to the open end of a line
+fn invert(positions: PositionList) -> PositionList {
+    let mut inverted = Vec::new();
+    let mut start = 0;
+    for range in normalize(positions) {
+        if range.start > start {
+            inverted.push(start..range.start);
+        }
+        start = start.max(range.end);
+    }
+    if start < usize::MAX {
+        inverted.push(start..usize::MAX);
+    }
+    inverted
+}
+/// Limits `range` to a line of `len` items
+fn clamp(range: &Range<usize>, len: usize) -> Range<usize> {
+    range.start.min(len)..range.end.min(len)
 }
 pub fn get_args() -> CutResult<Config> {
     Ok(Config::parse())
@@ -132,13 +571,61 @@ fn open(filename: &str) -> CutResult<Box<dyn BufRead>> {
         _ => Ok(Box::new(BufReader::new(File::open(filename)?))),
     }
 }
-fn parse_delimiter(delim: &str) -> Result<u8, String> {
-    let mut delim_iter = delim.bytes();
-    let result = delim_iter.next().ok_or(format_delim_err(delim));
-    match delim_iter.next() {
-        None => result,
-        Some(_) => Err(format_delim_err(delim)),
+fn parse_delimiter(delim: &str) -> Result<String, String> {
+    match delim {
+        "" => Err(format_delim_err(delim)),
+        _ => Ok(delim.to_string()),
+    }
+}
+fn parse_regex(pattern: &str) -> Result<Regex, String> {
+    match Regex::new(pattern) {
+        Ok(regex) if regex.is_match("") => Err(format_delim_err(pattern)),
+        Ok(regex) => Ok(regex),
+        Err(_) => Err(format_delim_err(pattern)),
+    }
+}
+/// Turns `10,5,20` into the character ranges of consecutive columns
+fn parse_widths(input: &str) -> Result<PositionList, String> {
+    let widths = match separated_list1(tag(","), decimal)(input) {
+        Ok(("", widths)) => widths,
+        Ok((rest, _)) => return Err(format_val_err(rest)),
+        Err(_) => return Err(format_val_err(input)),
+    };
+    let mut start: usize = 0;
+    widths
+        .iter()
+        .map(|width| {
+            let end = start
+                .checked_add(parse(width)?)
+                .ok_or_else(|| format_val_err(width))?;
+            let range = start..end;
+            start = end;
+            Ok(range)
+        })
+        .collect()
+}
+/// Reads the column names and ranges from `name,start,width` lines, counting
+/// from 1 like the positions. Blank lines and `#` comments are skipped.
+fn parse_spec(text: &str) -> Result<(StringRecord, PositionList), String> {
+    let mut names = StringRecord::new();
+    let mut columns = Vec::new();
+    for (idx, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let column = tuple((is_not(","), char(','), decimal, char(','), decimal))(line);
+        let (name, start, width) = match column {
+            Ok(("", (name, _, start, _, width))) => (name, parse(start)?, parse(width)?),
+            _ => return Err(format_spec_err(idx + 1, line)),
+        };
+        let end = (start - 1)
+            .checked_add(width)
+            .ok_or_else(|| format_val_err(width))?;
+        names.push_field(name.trim());
+        columns.push(start - 1..end);
     }
+    Ok((names, columns))
 }
 fn parse_position(input: &str) -> Result<PositionList, String> {
     let inputs = match separated_list0(tag(","), range_input)(input).map_err(format_val_err) {
@@ -152,6 +639,37 @@ fn parse_position(input: &str) -> Result<PositionList, String> {
         .collect();
     result
 }
+fn parse_fields(input: &str) -> Result<FieldList, String> {
+    if let Ok(positions) = parse_position(input) {
+        return Ok(positions.into_iter().map(Field::Position).collect());
+    }
+    let mut fields = Vec::new();
+    for part in input.split(',') {
+        match parse_position(part) {
+            _ if part.is_empty() => return Err(format_val_err(input)),
+            Ok(positions) => fields.extend(positions.into_iter().map(Field::Position)),
+            // Anything that starts like a number is meant to be one
+            Err(e) if part.starts_with(|c: char| c.is_ascii_digit() || c == '-') => return Err(e),
+            Err(_) => fields.push(Field::Name(part.to_string())),
+        }
+    }
+    Ok(fields)
+}
+/// Turns field names into positions using `header`, the first record of a file
+fn resolve_fields(fields: &[Field], header: Option<&StringRecord>) -> Result<PositionList, String> {
+    fields
+        .iter()
+        .map(|field| match (field, header) {
+            (Field::Position(range), _) => Ok(range.clone()),
+            (Field::Name(name), None) => Err(format_val_err(name)),
+            (Field::Name(name), Some(header)) => header
+                .iter()
+                .position(|column| column == name)
+                .map(|idx| idx..idx + 1)
+                .ok_or_else(|| format_name_err(name, header)),
+        })
+        .collect()
+}
 fn parsed_to_range(parsed: &str) -> Result<Range<usize>, String> {
     match parsed.split('-').collect::<Vec<_>>()[..] {
         ["", to] => Ok(0..parse(to)?),
@@ -187,6 +705,16 @@ fn format_range_err(from: usize, to: usize) -> String {
 fn format_delim_err(val: impl Display) -> String {
     format!("Invalid delimiter: \"{}\"", val)
 }
+fn format_spec_err(line_number: usize, line: &str) -> String {
+    format!(
+        "invalid column on line {}: \"{}\", expected name,start,width",
+        line_number, line
+    )
+}
+fn format_name_err(name: &str, header: &StringRecord) -> String {
+    let columns = header.iter().collect::<Vec<_>>().join(", ");
+    format!("unknown field name: \"{}\" (header has: {})", name, columns)
+}
 fn format_val_err(val: impl Display) -> String {
     format!("illegal list value: \"{}\"", val)
 }
@@ -207,10 +735,41 @@ fn decimal(input: &str) -> IResult<&str, &str> {
     recognize(many1(terminated(one_of("0123456789"), many0(char('_')))))(input)
 }
 #[cfg(test)]
+#[allow(clippy::single_range_in_vec_init)]
 mod unit_tests {
-    use super::{extract_bytes, extract_chars, extract_fields, parse_position};
+    use super::{
+        invert, normalize, parse_fields, parse_position, parse_spec, parse_widths, resolve_fields,
+        Field, Selection,
+    };
     use csv::StringRecord;
     use pretty_assertions::assert_eq;
+    use std::ops::Range;
+
+    fn extract_bytes(line: &str, byte_pos: &[Range<usize>]) -> String {
+        let selection = Selection::from(byte_pos.to_vec());
+        String::from_utf8_lossy(
+            &selection
+                .bytes(line.as_bytes())
+                .collect::<Vec<_>>()
+                .concat(),
+        )
+        .to_string()
+    }
+    fn extract_whole_chars(line: &str, byte_pos: &[Range<usize>]) -> String {
+        Selection::from(byte_pos.to_vec())
+            .whole_chars(line)
+            .collect()
+    }
+    fn extract_chars(line: &str, char_pos: &[Range<usize>]) -> String {
+        Selection::from(char_pos.to_vec()).chars(line).collect()
+    }
+    fn extract_graphemes(line: &str, char_pos: &[Range<usize>]) -> String {
+        Selection::from(char_pos.to_vec()).graphemes(line).collect()
+    }
+    fn extract_fields(record: &StringRecord, field_pos: &[Range<usize>]) -> Vec<String> {
+        let selection = Selection::from(field_pos.to_vec());
+        selection.fields(record).map(String::from).collect()
+    }
 
     #[test]
     fn test_parse_position0() {
@@ -307,7 +866,7 @@ mod unit_tests {
         // Zero is an error
         let res = parse_position("");
         assert!(res.is_ok());
-        assert_eq!(res.unwrap(), vec![]);
+        assert_eq!(res.unwrap(), Vec::<Range<usize>>::new());
     }
     #[test]
     fn test_parse_position_ok_single() {
@@ -364,7 +923,106 @@ mod unit_tests {
         assert!(res.is_ok());
         assert_eq!(res.unwrap(), vec![14..15, 18..20]);
     }
+    #[test]
+    fn test_parse_fields() {
+        assert_eq!(
+            parse_fields("1,3-4").unwrap(),
+            vec![Field::Position(0..1), Field::Position(2..4)]
+        );
+        assert_eq!(
+            parse_fields("title,2").unwrap(),
+            vec![Field::Name("title".to_string()), Field::Position(1..2)]
+        );
+        assert_eq!(
+            parse_fields("title,").unwrap_err().to_string(),
+            "illegal list value: \"title,\""
+        );
+        assert_eq!(
+            parse_fields("title,2-1").unwrap_err().to_string(),
+            "First number in range (2) must be lower than second number (1)"
+        );
+    }
+    #[test]
+    fn test_resolve_fields() {
+        let header = StringRecord::from(vec!["title", "year", "director"]);
+        let fields = vec![
+            Field::Name("year".to_string()),
+            Field::Position(0..1),
+            Field::Name("director".to_string()),
+        ];
+        assert_eq!(
+            resolve_fields(&fields, Some(&header)).unwrap(),
+            vec![1..2, 0..1, 2..3]
+        );
+        let unknown = vec![Field::Name("rating".to_string())];
+        assert_eq!(
+            resolve_fields(&unknown, Some(&header)).unwrap_err(),
+            "unknown field name: \"rating\" (header has: title, year, director)"
+        );
+        assert_eq!(
+            resolve_fields(&unknown, None).unwrap_err(),
+            "illegal list value: \"rating\""
+        );
+    }
 
+    #[test]
+    fn test_parse_widths() {
+        assert_eq!(
+            parse_widths("10,5,20").unwrap(),
+            vec![0..10, 10..15, 15..35]
+        );
+        assert_eq!(parse_widths("3").unwrap(), vec![0..3]);
+        assert_eq!(
+            parse_widths("10,0").unwrap_err(),
+            "illegal list value: \"0\""
+        );
+        assert_eq!(
+            parse_widths("10,a").unwrap_err(),
+            "illegal list value: \",a\""
+        );
+        assert!(parse_widths("").is_err());
+        assert_eq!(
+            parse_widths(&format!("{},1", usize::MAX)).unwrap_err(),
+            "illegal list value: \"1\""
+        );
+    }
+    #[test]
+    fn test_parse_spec() {
+        let (names, columns) = parse_spec("# name,start,width\nid,1,4\n\n name ,5,10\n").unwrap();
+        assert_eq!(names, StringRecord::from(vec!["id", "name"]));
+        assert_eq!(columns, vec![0..4, 4..14]);
+        assert_eq!(
+            parse_spec("id,1,4\nname,5\n").unwrap_err(),
+            "invalid column on line 2: \"name,5\", expected name,start,width"
+        );
+        assert_eq!(
+            parse_spec("id,0,4").unwrap_err(),
+            "illegal list value: \"0\""
+        );
+        assert_eq!(
+            parse_spec(&format!("id,3,{}", usize::MAX)).unwrap_err(),
+            format!("illegal list value: \"{}\"", usize::MAX)
+        );
+    }
+    #[test]
+    fn test_normalize() {
+        assert_eq!(normalize(vec![2..3, 0..1]), vec![0..1, 2..3]);
+        assert_eq!(normalize(vec![0..1, 0..1]), vec![0..1]);
+        assert_eq!(
+            normalize(vec![4..usize::MAX, 0..3, 2..5]),
+            vec![0..usize::MAX]
+        );
+        assert_eq!(normalize(vec![0..2, 2..4]), vec![0..4]);
+        assert_eq!(normalize(vec![]), Vec::<Range<usize>>::new());
+    }
+    #[test]
+    fn test_invert() {
+        assert_eq!(invert(vec![3..4]), vec![0..3, 4..usize::MAX]);
+        assert_eq!(invert(vec![4..usize::MAX]), vec![0..4]);
+        assert_eq!(invert(vec![0..1, 2..3, 1..2]), vec![3..usize::MAX]);
+        assert_eq!(invert(vec![0..usize::MAX]), Vec::<Range<usize>>::new());
+        assert_eq!(invert(vec![]), vec![0..usize::MAX]);
+    }
     #[test]
     fn test_extract_fields() {
         let rec = StringRecord::from(vec!["Captain", "Sham", "12345"]);
@@ -385,6 +1043,28 @@ mod unit_tests {
         assert_eq!(extract_chars("ábc", &[0..1, 1..2, 4..5]), "áb".to_string());
     }
     #[test]
+    fn test_extract_graphemes() {
+        // "é" as "e" with a combining accent, and a family emoji of four people
+        let line = "e\u{301}a\u{1F468}\u{200D}\u{1F469}\u{200D}\u{1F467}\u{200D}\u{1F466}b";
+        assert_eq!(extract_chars(line, &[0..1]), "e");
+        assert_eq!(extract_graphemes(line, &[0..1]), "e\u{301}");
+        assert_eq!(
+            extract_graphemes(line, &[2..3]),
+            "\u{1F468}\u{200D}\u{1F469}\u{200D}\u{1F467}\u{200D}\u{1F466}"
+        );
+        assert_eq!(extract_graphemes(line, &[3..4, 1..2]), "ba");
+        assert_eq!(extract_graphemes(line, &[5..6]), "");
+    }
+    #[test]
+    fn test_extract_whole_chars() {
+        assert_eq!(extract_whole_chars("ábc", &[0..1]), "");
+        assert_eq!(extract_whole_chars("ábc", &[0..2]), "á");
+        assert_eq!(extract_whole_chars("ábc", &[1..3]), "b");
+        assert_eq!(extract_whole_chars("ábc", &[0..4]), "ábc");
+        assert_eq!(extract_whole_chars("ábc", &[3..4, 2..3]), "cb");
+        assert_eq!(extract_whole_chars("ábc", &[0..2, 5..6]), "á");
+    }
+    #[test]
     fn test_extract_bytes() {
         assert_eq!(extract_bytes("ábc", &[0..1]), "�".to_string());
         assert_eq!(extract_bytes("ábc", &[0..2]), "á".to_string());
@@ -1,6 +1,9 @@
 fn main() {
-    if let Err(e) = cutr::get_args().and_then(cutr::run) {
-        eprintln!("{}", e);
-        std::process::exit(1);
+    match cutr::get_args().and_then(cutr::run) {
+        Ok(code) => std::process::exit(code),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
     }
 }
@@ -9,6 +9,8 @@ const PRG: &str = "cutr";
 const CSV: &str = "tests/inputs/movies1.csv";
 const TSV: &str = "tests/inputs/movies1.tsv";
 const BOOKS: &str = "tests/inputs/books.tsv";
+const FIXED: &str = "tests/inputs/movies1.fixed";
+const SPEC: &str = "tests/inputs/movies1.spec";
 
 // --------------------------------------------------
 fn random_string() -> String {
@@ -37,7 +39,8 @@ fn skips_bad_file() -> TestResult {
     Command::cargo_bin(PRG)?
         .args(["-f", "1", CSV, &bad, TSV])
         .assert()
-        .success()
+        .code(1)
+        .stdout(predicate::str::ends_with("Les Misérables\n"))
         .stderr(predicate::str::is_match(expected)?);
     Ok(())
 }
@@ -100,7 +103,28 @@ fn dies_empty_delimiter() -> TestResult {
 // --------------------------------------------------
 #[test]
 fn dies_bad_delimiter() -> TestResult {
-    dies(&[CSV, "-f", "1", "-d", ",,"], "Invalid delimiter")
+    dies(
+        &[CSV, "-f", "1", "--regex-delimiter", "("],
+        "Invalid delimiter",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_empty_regex_delimiter() -> TestResult {
+    dies(
+        &[CSV, "-f", "1", "--regex-delimiter", "x*"],
+        "Invalid delimiter",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_delimiter_and_regex_delimiter() -> TestResult {
+    dies(
+        &[CSV, "-f", "1", "-d", ",", "--regex-delimiter", ","],
+        "cannot be used with",
+    )
 }
 
 // --------------------------------------------------
@@ -332,5 +356,425 @@ fn tsv_c1_8() -> TestResult {
 // --------------------------------------------------
 #[test]
 fn repeated_value() -> TestResult {
-    run(&[BOOKS, "-c", "1,1"], "tests/expected/books.c1,1.out")
+    run(
+        &[BOOKS, "-c", "1,1", "--reorder"],
+        "tests/expected/books.c1,1.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn repeated_value_selected_once() -> TestResult {
+    run(&[BOOKS, "-c", "1,1"], "tests/expected/books.c1,1.dedup.out")
+}
+
+// --------------------------------------------------
+#[test]
+fn fields_in_line_order() -> TestResult {
+    run(&[BOOKS, "-f", "3,1"], "tests/expected/books.f3,1.out")
+}
+
+// --------------------------------------------------
+#[test]
+fn fields_reordered() -> TestResult {
+    run(
+        &[BOOKS, "-f", "3,1", "--reorder"],
+        "tests/expected/books.f3,1.reorder.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn csv_header_names() -> TestResult {
+    run(
+        &[CSV, "--header", "-f", "year,title", "-d", ",", "--reorder"],
+        "tests/expected/movies1.csv.fyear,title.header.dcomma.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn tsv_header_names_and_positions() -> TestResult {
+    run(
+        &[BOOKS, "--header", "-f", "Title,1"],
+        "tests/expected/books.tsv.fTitle,1.header.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn header_unknown_name() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args([CSV, "--header", "-f", "rating", "-d", ","])
+        .assert()
+        .code(1)
+        .stdout("")
+        .stderr(format!(
+            "{}: unknown field name: \"rating\" (header has: title, year, director)\n",
+            CSV
+        ));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_name_without_header() -> TestResult {
+    dies(
+        &[CSV, "-f", "title", "-d", ","],
+        "illegal list value: \"title\"",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_header_without_fields() -> TestResult {
+    dies(&[CSV, "--header", "-c", "1"], "cannot be used with")
+}
+
+// --------------------------------------------------
+#[test]
+fn complement_fields() -> TestResult {
+    run(
+        &[BOOKS, "-f", "2", "--complement"],
+        "tests/expected/books.tsv.f2.complement.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn complement_open_ended_chars() -> TestResult {
+    run(
+        &[BOOKS, "-c", "3-", "--complement"],
+        "tests/expected/books.tsv.c3-.complement.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn complement_bytes() -> TestResult {
+    run(
+        &[BOOKS, "-b", "1,3", "--complement"],
+        "tests/expected/books.tsv.b1,3.complement.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_complement_reorder() -> TestResult {
+    dies(
+        &[BOOKS, "-f", "2", "--complement", "--reorder"],
+        "cannot be used with",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn multi_char_delimiter() -> TestResult {
+    run(
+        &["tests/inputs/movies1.psv", "-f", "3,1", "-d", " | "],
+        "tests/expected/movies1.psv.f1,3.dpipe.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn regex_delimiter() -> TestResult {
+    run(
+        &[
+            "tests/inputs/ratings.txt",
+            "-f",
+            "1,3",
+            "--regex-delimiter",
+            r"\s+",
+            "--output-delimiter",
+            ",",
+        ],
+        "tests/expected/ratings.txt.f1,3.regex.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn regex_delimiter_uneven() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--regex-delimiter", r"\s+", "-f", "1-3"])
+        .write_stdin("a  b   c\nd e\n")
+        .assert()
+        .success()
+        .stdout("a b c\nd e\n");
+    Command::cargo_bin(PRG)?
+        .args(["--regex-delimiter", ";+", "-f", "1,2"])
+        .write_stdin("x y;;z\n")
+        .assert()
+        .success()
+        .stdout("x y z\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn multi_char_delimiter_uneven() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-d", "::", "-f", "1-3", "--output-delimiter", ","])
+        .write_stdin("a::b::c\nd::e\n")
+        .assert()
+        .success()
+        .stdout("a,b,c\nd,e\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn output_delimiter() -> TestResult {
+    run(
+        &[CSV, "-f", "1,3", "-d", ",", "--output-delimiter", " | "],
+        "tests/expected/movies1.csv.f1,3.dcomma.opipe.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn raw_fields() -> TestResult {
+    run(
+        &["tests/inputs/quotes.tsv", "-f", "1,3", "--raw"],
+        "tests/expected/quotes.tsv.f1,3.raw.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn raw_keeps_quotes() -> TestResult {
+    run(
+        &["tests/inputs/quotes.tsv", "-f", "2", "--raw"],
+        "tests/expected/quotes.tsv.f2.raw.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn raw_only_delimited() -> TestResult {
+    run(
+        &["tests/inputs/quotes.tsv", "-f", "1,3", "--raw", "-s"],
+        "tests/expected/quotes.tsv.f1,3.raw.s.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn only_delimited() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-d", ",", "-f", "2", "-s"])
+        .write_stdin("a,b\nnodelim\nc,d\n")
+        .assert()
+        .success()
+        .stdout("b\nd\n");
+    Command::cargo_bin(PRG)?
+        .args(["-d", ",", "-f", "1-3", "-s"])
+        .write_stdin("a,b,c\nd,e\nnodelim\n")
+        .assert()
+        .success()
+        .stdout("a,b,c\nd,e\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn graphemes() -> TestResult {
+    run(
+        &["tests/inputs/graphemes.txt", "-c", "1-2", "--graphemes"],
+        "tests/expected/graphemes.txt.c1-2.graphemes.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn bytes_no_split() -> TestResult {
+    run(
+        &[BOOKS, "-b", "1", "-n"],
+        "tests/expected/books.tsv.b1.n.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn bytes_no_split_whole_char() -> TestResult {
+    run(
+        &[BOOKS, "-b", "1-2", "-n"],
+        "tests/expected/books.tsv.b1-2.n.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_graphemes_with_bytes() -> TestResult {
+    dies(&[BOOKS, "-b", "1", "--graphemes"], "cannot be used with")
+}
+
+// --------------------------------------------------
+#[test]
+fn fixed_widths() -> TestResult {
+    run(
+        &[FIXED, "--widths", "20,4,20", "-f", "3,1"],
+        "tests/expected/movies1.fixed.f1,3.widths.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn fixed_spec_csv() -> TestResult {
+    run(
+        &[
+            FIXED,
+            "--spec",
+            SPEC,
+            "-f",
+            "director,year",
+            "--format",
+            "csv",
+        ],
+        "tests/expected/movies1.fixed.fyear,director.spec.csv.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn format_tsv_escapes_instead_of_quoting() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-d", ",", "-f", "1,2", "--format", "tsv"])
+        .write_stdin("a,say \"hi\"\n\"b\tc\",\"d\\e\nf\"\n")
+        .assert()
+        .success()
+        .stdout("a\tsay \"hi\"\nb\\tc\td\\\\e\\nf\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn fixed_spec_json() -> TestResult {
+    run(
+        &[
+            FIXED,
+            "--spec",
+            SPEC,
+            "-f",
+            "title,year",
+            "--format",
+            "json",
+        ],
+        "tests/expected/movies1.fixed.ftitle,year.spec.json.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_bad_spec() -> TestResult {
+    dies(
+        &[FIXED, "--spec", CSV, "-f", "1"],
+        "invalid column on line 1: \"title,year,director\"",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_zero_width() -> TestResult {
+    dies(
+        &[FIXED, "--widths", "20,0", "-f", "1"],
+        "illegal list value: \"0\"",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn json_arrays() -> TestResult {
+    run(
+        &[CSV, "-f", "1,2", "-d", ",", "--format", "json"],
+        "tests/expected/movies1.csv.f1-2.dcomma.json.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn json_objects_with_header() -> TestResult {
+    run(
+        &[BOOKS, "--header", "-f", "Title,1", "--format", "json"],
+        "tests/expected/books.tsv.fTitle,1.header.json.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn ndjson_objects_with_header() -> TestResult {
+    run(
+        &[
+            CSV,
+            "--header",
+            "-f",
+            "year,title",
+            "-d",
+            ",",
+            "--format",
+            "ndjson",
+            "--reorder",
+        ],
+        "tests/expected/movies1.csv.fyear,title.header.ndjson.out",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_format_with_output_delimiter() -> TestResult {
+    dies(
+        &[
+            CSV,
+            "-f",
+            "1",
+            "--format",
+            "ndjson",
+            "--output-delimiter",
+            ";",
+        ],
+        "cannot be used with",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn reads_stdin_by_default() -> TestResult {
+    let input = fs::read_to_string(TSV)?;
+    let expected = fs::read_to_string("tests/expected/movies1.tsv.f1.out")?;
+    Command::cargo_bin(PRG)?
+        .args(["-f", "1"])
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout(expected);
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn reads_stdin_from_dash() -> TestResult {
+    let input = fs::read_to_string(BOOKS)?;
+    let expected = fs::read_to_string("tests/expected/books.c1,1.dedup.out")?;
+    Command::cargo_bin(PRG)?
+        .args(["-c", "1", "-"])
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout(expected);
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn fails_on_bad_file_in_every_mode() -> TestResult {
+    let bad = gen_bad_file();
+    for mode in ["-b", "-c", "-f"] {
+        Command::cargo_bin(PRG)?
+            .args([mode, "1", BOOKS, &bad])
+            .assert()
+            .code(1)
+            .stderr(predicate::str::starts_with(format!("{}: ", bad)));
+    }
+    Ok(())
 }
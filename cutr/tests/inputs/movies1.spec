@@ -0,0 +1,5 @@
+# name,start,width
+title,1,20
+year,21,4
+
+director,25,20
@@ -0,0 +1,53 @@
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use std::io::{self, BufRead, Cursor, Write};
+
+const LINES: usize = 200_000;
+
+/// The original implementation: collect every matching line of the file into
+/// a `Vec<String>` before anything is printed.
+fn collect_lines<T: BufRead>(file: T, pattern: &regex::Regex) -> Vec<String> {
+    file.lines()
+        .map_while(Result::ok)
+        .filter(|line| pattern.is_match(line))
+        .collect()
+}
+
+fn input() -> Vec<u8> {
+    let words = [
+        "The bustle in a house",
+        "The morning after death",
+        "fox",
+        "lazy dog",
+    ];
+    let mut text = Vec::new();
+    for i in 0..LINES {
+        writeln!(text, "{} line {}", words[i % words.len()], i).unwrap();
+    }
+    text
+}
+
+fn search(c: &mut Criterion) {
+    let text = input();
+    let mut group = c.benchmark_group("search");
+    group.throughput(Throughput::Bytes(text.len() as u64));
+
+    let pattern = regex::Regex::new("morning").unwrap();
+    group.bench_function("collect", |b| {
+        b.iter(|| {
+            let mut out = io::sink();
+            for line in collect_lines(Cursor::new(&text), &pattern) {
+                writeln!(out, "{}", line).unwrap();
+            }
+        })
+    });
+
+    let pattern = regex::bytes::Regex::new("morning").unwrap();
+    group.bench_function("streaming", |b| {
+        b.iter(|| grepr::search_reader(Cursor::new(&text), &pattern, &mut io::sink()).unwrap())
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, search);
+criterion_main!(benches);
@@ -1,26 +1,70 @@
-use clap::Parser;
-use regex::{Error as RegexError, Regex, RegexBuilder};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use bzip2::bufread::MultiBzDecoder;
+use clap::{Parser, ValueEnum};
+use flate2::bufread::MultiGzDecoder;
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use ignore::WalkBuilder;
+use regex::bytes::{Regex, RegexBuilder};
+use serde_json::{json, Value};
+use std::collections::VecDeque;
+use std::env;
 use std::error::Error;
-use std::fs::File;
-use std::io::{self, BufRead, BufReader};
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, IsTerminal, LineWriter, Read, Write};
+use std::iter;
 use std::path::Path;
-use walkdir::WalkDir;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+use xz2::bufread::XzDecoder;
 
-type GrepResult<T> = Result<T, Box<dyn Error>>;
-type InsensitiveRegex = Regex;
+type GrepResult<T> = Result<T, Box<dyn Error + Send + Sync>>;
 
 #[derive(Debug, Parser)]
 #[command(author, version, about)]
 pub struct Config {
-    ///Search pattern
-    #[arg(name = "PATTERN", required = true, value_parser = parse_regex)]
-    pattern: (Regex, InsensitiveRegex),
-    ///Input file(s)
-    #[arg(name = "FILES", default_value = "-")]
+    ///Search pattern, taken as a file when -e or -f is given
+    #[arg(name = "PATTERN", required_unless_present_any = ["regexp", "pattern_files"])]
+    pattern: Option<String>,
+    ///Input file(s), defaults to "-"
+    #[arg(name = "FILES")]
     files: Vec<String>,
+    ///Search pattern, can be repeated
+    #[arg(short = 'e', long, value_name = "PATTERN")]
+    regexp: Vec<String>,
+    ///Read search patterns from FILE, one per line
+    #[arg(short = 'f', long = "file", value_name = "FILE")]
+    pattern_files: Vec<String>,
+    ///Treat patterns as fixed strings
+    #[arg(short = 'F', long)]
+    fixed_strings: bool,
+    ///Match only whole words
+    #[arg(short = 'w', long)]
+    word_regexp: bool,
+    ///Match only whole lines
+    #[arg(short = 'x', long)]
+    line_regexp: bool,
     ///Recursive search
     #[arg(short, long)]
     recursive: bool,
+    ///Search only files whose name matches GLOB
+    #[arg(long, value_name = "GLOB")]
+    include: Vec<String>,
+    ///Skip files whose name matches GLOB
+    #[arg(long, value_name = "GLOB")]
+    exclude: Vec<String>,
+    ///Skip directories whose name matches GLOB when recursing
+    #[arg(long, value_name = "GLOB")]
+    exclude_dir: Vec<String>,
+    ///Don't respect .gitignore and .ignore files
+    #[arg(long)]
+    no_ignore: bool,
+    ///Number of threads searching files, defaults to the number of CPUs.
+    ///With more than one, each file's matches are buffered until it is printed
+    #[arg(short = 'j', long, value_name = "NUM")]
+    threads: Option<usize>,
     ///Case insensiive
     #[arg(short, long)]
     insensitive: bool,
@@ -30,117 +74,970 @@ pub struct Config {
     ///Invert match
     #[arg(short = 'v', long)]
     invert_match: bool,
+    ///Stop reading a file after NUM matching lines
+    #[arg(short = 'm', long, value_name = "NUM")]
+    max_count: Option<usize>,
+    ///Print NUM lines of trailing context
+    #[arg(short = 'A', long, value_name = "NUM")]
+    after_context: Option<usize>,
+    ///Print NUM lines of leading context
+    #[arg(short = 'B', long, value_name = "NUM")]
+    before_context: Option<usize>,
+    ///Print NUM lines of leading and trailing context
+    #[arg(short = 'C', long, value_name = "NUM")]
+    context: Option<usize>,
+    ///Prefix each line with its line number
+    #[arg(short = 'n', long)]
+    line_number: bool,
+    ///Prefix each line with its byte offset
+    #[arg(short = 'b', long)]
+    byte_offset: bool,
+    ///Print only the matched parts of a line
+    #[arg(short = 'o', long)]
+    only_matching: bool,
+    ///Print only the names of files with matches
+    #[arg(short = 'l', long, conflicts_with = "files_without_match")]
+    files_with_matches: bool,
+    ///Print only the names of files without matches
+    #[arg(short = 'L', long)]
+    files_without_match: bool,
+    ///Print nothing, exit with zero status on the first match
+    #[arg(short, long)]
+    quiet: bool,
+    ///Highlight matches, filenames and line numbers
+    #[arg(long, value_name = "WHEN", default_value = "auto")]
+    color: ColorChoice,
+    ///Process a binary file as if it were text
+    #[arg(short = 'a', long)]
+    text: bool,
+    ///How to handle files with a NUL byte in their first block
+    #[arg(long, value_name = "TYPE", default_value = "binary")]
+    binary_files: BinaryFiles,
+    ///Print results as JSON Lines
+    #[arg(
+        long,
+        conflicts_with_all = ["count", "files_with_matches", "files_without_match", "quiet", "only_matching"]
+    )]
+    json: bool,
+    ///Search inside gzip, bzip2, xz and zstd compressed files
+    #[arg(short = 'z', long)]
+    search_zip: bool,
+}
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+enum BinaryFiles {
+    Binary,
+    Text,
+    WithoutMatch,
+}
+#[derive(Debug, PartialEq)]
+enum Line {
+    Match(LineInfo),
+    Context(LineInfo),
+    Break,
+}
+#[derive(Debug, PartialEq)]
+struct LineInfo {
+    number: usize,
+    offset: usize,
+    text: Vec<u8>,
+}
+struct SearchOptions<'a> {
+    pattern: &'a Regex,
+    invert_match: bool,
+    before_context: usize,
+    after_context: usize,
+    /// Whether any context was asked for, even zero lines separates hunks
+    context: bool,
+    max_count: Option<usize>,
+}
+impl SearchOptions<'_> {
+    fn has_context(&self) -> bool {
+        self.context
+    }
+}
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+enum ColorChoice {
+    Auto,
+    Always,
+    Never,
+}
+const MATCH_COLOR: &str = "\x1b[01;31m";
+const FILENAME_COLOR: &str = "\x1b[35m";
+const NUMBER_COLOR: &str = "\x1b[32m";
+const SEPARATOR_COLOR: &str = "\x1b[36m";
+const RESET_COLOR: &str = "\x1b[0m";
+/// Formats everything grepr writes to stdout, so the filename, line number
+/// and byte offset prefix is composed in one place.
+#[derive(Clone, Copy)]
+struct Printer<'a> {
+    pattern: &'a Regex,
+    with_filename: bool,
+    line_number: bool,
+    byte_offset: bool,
+    only_matching: bool,
+    color: bool,
+}
+impl Printer<'_> {
+    fn paint(&self, text: &str, color: &str) -> String {
+        if self.color {
+            format!("{color}{text}{RESET_COLOR}")
+        } else {
+            text.to_string()
+        }
+    }
+    fn prefix(&self, filename: &str, number: usize, offset: usize, separator: char) -> String {
+        let separator = self.paint(&separator.to_string(), SEPARATOR_COLOR);
+        let mut prefix = String::new();
+        if self.with_filename {
+            prefix.push_str(&self.paint(filename, FILENAME_COLOR));
+            prefix.push_str(&separator);
+        }
+        if self.line_number {
+            prefix.push_str(&self.paint(&number.to_string(), NUMBER_COLOR));
+            prefix.push_str(&separator);
+        }
+        if self.byte_offset {
+            prefix.push_str(&self.paint(&offset.to_string(), NUMBER_COLOR));
+            prefix.push_str(&separator);
+        }
+        prefix
+    }
+    fn highlight(&self, text: &[u8]) -> Vec<u8> {
+        if !self.color {
+            return text.to_vec();
+        }
+        let mut highlighted = Vec::with_capacity(text.len());
+        let mut last = 0;
+        for m in self
+            .pattern
+            .find_iter(strip_cr(text))
+            .filter(|m| !m.is_empty())
+        {
+            highlighted.extend_from_slice(&text[last..m.start()]);
+            highlighted.extend_from_slice(MATCH_COLOR.as_bytes());
+            highlighted.extend_from_slice(m.as_bytes());
+            highlighted.extend_from_slice(RESET_COLOR.as_bytes());
+            last = m.end();
+        }
+        highlighted.extend_from_slice(&text[last..]);
+        highlighted
+    }
+    fn write_line(&self, out: &mut dyn Write, prefix: &str, text: &[u8]) -> io::Result<()> {
+        out.write_all(prefix.as_bytes())?;
+        out.write_all(text)?;
+        out.write_all(b"\n")
+    }
+    fn print_line(&self, out: &mut dyn Write, filename: &str, line: &Line) -> io::Result<()> {
+        match line {
+            Line::Match(info) if self.only_matching => {
+                for m in self.pattern.find_iter(strip_cr(&info.text)) {
+                    if !m.is_empty() {
+                        let offset = info.offset + m.start();
+                        let prefix = self.prefix(filename, info.number, offset, ':');
+                        self.write_line(out, &prefix, &self.highlight(m.as_bytes()))?;
+                    }
+                }
+                Ok(())
+            }
+            Line::Match(info) => {
+                let prefix = self.prefix(filename, info.number, info.offset, ':');
+                self.write_line(out, &prefix, &self.highlight(&info.text))
+            }
+            // Only the matched parts are printed, so there is no context to show
+            Line::Context(_) if self.only_matching => Ok(()),
+            Line::Context(info) => {
+                let prefix = self.prefix(filename, info.number, info.offset, '-');
+                self.write_line(out, &prefix, &info.text)
+            }
+            Line::Break => self.print_break(out),
+        }
+    }
+    fn print_break(&self, out: &mut dyn Write) -> io::Result<()> {
+        writeln!(out, "{}", self.paint("--", SEPARATOR_COLOR))
+    }
+    fn print_binary_match(&self, out: &mut dyn Write, filename: &str) -> io::Result<()> {
+        writeln!(out, "Binary file {} matches", filename)
+    }
+    fn print_filename(&self, out: &mut dyn Write, filename: &str) -> io::Result<()> {
+        writeln!(out, "{}", self.paint(filename, FILENAME_COLOR))
+    }
+    fn print_count(&self, out: &mut dyn Write, filename: &str, count: usize) -> io::Result<()> {
+        if self.with_filename {
+            let separator = self.paint(":", SEPARATOR_COLOR);
+            let filename = self.paint(filename, FILENAME_COLOR);
+            writeln!(out, "{}{}{}", filename, separator, count)
+        } else {
+            writeln!(out, "{}", count)
+        }
+    }
+}
+/// Receives the lines of each file as they are found. `Printer` writes them
+/// the way grep does, `JsonSink` as one JSON object per event.
+trait Sink: Sync {
+    /// Called before the first line printed for a file
+    fn begin(&self, out: &mut dyn Write, filename: &str) -> io::Result<()>;
+    fn line(&self, out: &mut dyn Write, filename: &str, line: &Line) -> io::Result<()>;
+    fn binary_match(&self, out: &mut dyn Write, filename: &str) -> io::Result<()>;
+    /// Called after the last line of a file if `begin` was
+    fn end(&self, out: &mut dyn Write, filename: &str, stats: &FileStats) -> io::Result<()>;
+    fn summary(&self, out: &mut dyn Write, summary: &Summary, elapsed: Duration) -> io::Result<()>;
+}
+impl Sink for Printer<'_> {
+    fn begin(&self, _out: &mut dyn Write, _filename: &str) -> io::Result<()> {
+        Ok(())
+    }
+    fn line(&self, out: &mut dyn Write, filename: &str, line: &Line) -> io::Result<()> {
+        self.print_line(out, filename, line)
+    }
+    fn binary_match(&self, out: &mut dyn Write, filename: &str) -> io::Result<()> {
+        self.print_binary_match(out, filename)
+    }
+    fn end(&self, _out: &mut dyn Write, _filename: &str, _stats: &FileStats) -> io::Result<()> {
+        Ok(())
+    }
+    fn summary(
+        &self,
+        _out: &mut dyn Write,
+        _summary: &Summary,
+        _elapsed: Duration,
+    ) -> io::Result<()> {
+        Ok(())
+    }
+}
+/// Writes results in the JSON Lines format of ripgrep's `--json`
+struct JsonSink<'a> {
+    pattern: &'a Regex,
+}
+impl JsonSink<'_> {
+    fn write_event(&self, out: &mut dyn Write, kind: &str, data: Value) -> io::Result<()> {
+        serde_json::to_writer(&mut *out, &json!({ "type": kind, "data": data }))?;
+        out.write_all(b"\n")
+    }
+    fn line_event(&self, filename: &str, info: &LineInfo, submatches: bool) -> Value {
+        let submatches: Vec<Value> = if submatches {
+            self.pattern
+                .find_iter(strip_cr(&info.text))
+                .filter(|m| !m.is_empty())
+                .map(|m| json!({ "match": json_data(m.as_bytes()), "start": m.start(), "end": m.end() }))
+                .collect()
+        } else {
+            Vec::new()
+        };
+        let mut text = info.text.clone();
+        text.push(b'\n');
+        json!({
+            "path": { "text": filename },
+            "lines": json_data(&text),
+            "line_number": info.number,
+            "absolute_offset": info.offset,
+            "submatches": submatches,
+        })
+    }
+}
+/// Text is written as is, anything that is not UTF-8 base64 encoded
+fn json_data(bytes: &[u8]) -> Value {
+    match std::str::from_utf8(bytes) {
+        Ok(text) => json!({ "text": text }),
+        Err(_) => json!({ "bytes": BASE64.encode(bytes) }),
+    }
+}
+fn json_duration(elapsed: Duration) -> Value {
+    json!({
+        "secs": elapsed.as_secs(),
+        "nanos": elapsed.subsec_nanos(),
+        "human": format!("{:.6}s", elapsed.as_secs_f64()),
+    })
+}
+impl Sink for JsonSink<'_> {
+    fn begin(&self, out: &mut dyn Write, filename: &str) -> io::Result<()> {
+        self.write_event(out, "begin", json!({ "path": { "text": filename } }))
+    }
+    fn line(&self, out: &mut dyn Write, filename: &str, line: &Line) -> io::Result<()> {
+        match line {
+            Line::Match(info) => {
+                self.write_event(out, "match", self.line_event(filename, info, true))
+            }
+            Line::Context(info) => {
+                self.write_event(out, "context", self.line_event(filename, info, false))
+            }
+            Line::Break => Ok(()),
+        }
+    }
+    fn binary_match(&self, _out: &mut dyn Write, _filename: &str) -> io::Result<()> {
+        Ok(())
+    }
+    fn end(&self, out: &mut dyn Write, filename: &str, stats: &FileStats) -> io::Result<()> {
+        let data = json!({
+            "path": { "text": filename },
+            "binary": stats.binary,
+            "stats": {
+                "elapsed": json_duration(stats.elapsed),
+                "matched_lines": stats.matched_lines,
+            },
+        });
+        self.write_event(out, "end", data)
+    }
+    fn summary(&self, out: &mut dyn Write, summary: &Summary, elapsed: Duration) -> io::Result<()> {
+        let data = json!({
+            "elapsed_total": json_duration(elapsed),
+            "stats": {
+                "searches": summary.searches,
+                "searches_with_match": summary.searches_with_match,
+                "matched_lines": summary.matched_lines,
+            },
+        });
+        self.write_event(out, "summary", data)
+    }
+}
+/// What gets printed for each searched file
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum OutputMode {
+    Lines,
+    Count,
+    FilesWithMatches,
+    FilesWithoutMatch,
+    Quiet,
+}
+/// Everything needed to search a single file, shared by the worker threads
+struct Searcher<'a> {
+    options: SearchOptions<'a>,
+    printer: Printer<'a>,
+    /// Where the lines go in `OutputMode::Lines`
+    sink: Box<dyn Sink + 'a>,
+    mode: OutputMode,
+    binary_files: BinaryFiles,
+    search_zip: bool,
+}
+/// The outcome of searching one file
+#[derive(Default)]
+struct FileResult {
+    error: Option<String>,
+    matched_lines: usize,
+    printed: bool,
 }
-pub fn run(config: Config) -> GrepResult<()> {
-    let entries = find_files(&config.files, config.recursive);
-    let pattern = if config.insensitive {
-        // maybe use a struct
-        config.pattern.1
+/// Reported to the sink at the end of every file it printed lines for
+struct FileStats {
+    matched_lines: usize,
+    binary: bool,
+    elapsed: Duration,
+}
+impl Searcher<'_> {
+    /// Searches one entry of the walk, writing its output to `out` as it is
+    /// found. With `separate` the first hunk is preceded by a `--` separator.
+    fn search(
+        &self,
+        entry: Result<String, String>,
+        out: &mut dyn Write,
+        separate: bool,
+    ) -> FileResult {
+        let mut result = FileResult::default();
+        let filename = match entry {
+            Err(e) => {
+                result.error = Some(e);
+                return result;
+            }
+            Ok(filename) => filename,
+        };
+        match self.search_file(&filename, out, separate) {
+            Err(e) => result.error = Some(format!("{}: {}", filename, e)),
+            Ok((count, printed)) => {
+                result.matched_lines = count;
+                result.printed = printed;
+            }
+        }
+        result
+    }
+    /// Returns the number of matching lines and whether anything was printed
+    fn search_file(
+        &self,
+        filename: &str,
+        out: &mut dyn Write,
+        separate: bool,
+    ) -> GrepResult<(usize, bool)> {
+        let start = Instant::now();
+        let mut file = open(filename, self.search_zip)?;
+        // Like grep, a NUL byte in the first block marks the file as binary
+        let binary = self.binary_files != BinaryFiles::Text && file.fill_buf()?.contains(&0);
+        if binary && self.binary_files == BinaryFiles::WithoutMatch {
+            return Ok((0, false));
+        }
+        // Only whether a file matches is needed, so stop reading at the first hit
+        let first_match = SearchOptions {
+            before_context: 0,
+            after_context: 0,
+            max_count: Some(1),
+            ..self.options
+        };
+        let (printer, sink) = (&self.printer, &self.sink);
+        let file_stats = |matched_lines| FileStats {
+            matched_lines,
+            binary,
+            elapsed: start.elapsed(),
+        };
+        match self.mode {
+            OutputMode::Quiet => Ok((find_lines(file, &first_match, |_| Ok(()))?, false)),
+            OutputMode::FilesWithMatches | OutputMode::FilesWithoutMatch => {
+                let count = find_lines(file, &first_match, |_| Ok(()))?;
+                let listed = (count > 0) == (self.mode == OutputMode::FilesWithMatches);
+                if listed {
+                    printer.print_filename(out, filename)?;
+                }
+                Ok((count, listed))
+            }
+            OutputMode::Count => {
+                let count = find_lines(file, &self.options, |_| Ok(()))?;
+                printer.print_count(out, filename, count)?;
+                Ok((count, true))
+            }
+            OutputMode::Lines if binary => {
+                let count = find_lines(file, &first_match, |_| Ok(()))?;
+                if count > 0 {
+                    sink.begin(out, filename)?;
+                    sink.binary_match(out, filename)?;
+                    sink.end(out, filename, &file_stats(count))?;
+                }
+                Ok((count, count > 0))
+            }
+            OutputMode::Lines => {
+                let separate = separate && self.options.has_context();
+                let mut printed = false;
+                let count = find_lines(file, &self.options, |line| {
+                    if !printed {
+                        if separate {
+                            sink.line(out, filename, &Line::Break)?;
+                        }
+                        sink.begin(out, filename)?;
+                        printed = true;
+                    }
+                    Ok(sink.line(out, filename, &line)?)
+                })?;
+                if printed {
+                    sink.end(out, filename, &file_stats(count))?;
+                }
+                Ok((count, printed))
+            }
+        }
+    }
+}
+/// What happened across all searched files, which decides the exit status
+#[derive(Default)]
+struct Summary {
+    printed: bool,
+    failed: bool,
+    searches: usize,
+    searches_with_match: usize,
+    matched_lines: usize,
+}
+impl Summary {
+    fn add(&mut self, result: &FileResult) {
+        match &result.error {
+            Some(e) => {
+                eprintln!("{}", e);
+                self.failed = true;
+            }
+            None => self.searches += 1,
+        }
+        if result.matched_lines > 0 {
+            self.searches_with_match += 1;
+        }
+        self.matched_lines += result.matched_lines;
+        self.printed |= result.printed;
+    }
+    fn matched(&self) -> bool {
+        self.searches_with_match > 0
+    }
+    /// Like grep: 0 if any line was selected, 1 if none was, 2 on errors
+    fn exit_code(&self, quiet: bool) -> i32 {
+        match (self.matched(), self.failed) {
+            (true, _) if quiet => 0,
+            (_, true) => 2,
+            (true, false) => 0,
+            (false, false) => 1,
+        }
+    }
+}
+pub fn run(config: Config) -> GrepResult<i32> {
+    let start = Instant::now();
+    let pattern = build_pattern(&config)?;
+    let mut files = config.files.clone();
+    if !config.regexp.is_empty() || !config.pattern_files.is_empty() {
+        files.splice(0..0, config.pattern.clone());
+    }
+    if files.is_empty() {
+        files.push("-".to_string());
+    }
+    let walk_options = WalkOptions {
+        recursive: config.recursive,
+        no_ignore: config.no_ignore,
+        include: build_globs(&config.include)?,
+        exclude: build_globs(&config.exclude)?,
+        exclude_dir: build_globs(&config.exclude_dir)?,
+    };
+    let printer = Printer {
+        pattern: &pattern,
+        with_filename: files.len() > 1
+            || config.recursive && files.iter().any(|f| Path::new(f).is_dir()),
+        line_number: config.line_number,
+        byte_offset: config.byte_offset,
+        only_matching: config.only_matching,
+        color: use_color(config.color),
+    };
+    let searcher = Searcher {
+        options: SearchOptions {
+            pattern: &pattern,
+            invert_match: config.invert_match,
+            before_context: config.before_context.or(config.context).unwrap_or(0),
+            after_context: config.after_context.or(config.context).unwrap_or(0),
+            context: config.before_context.is_some()
+                || config.after_context.is_some()
+                || config.context.is_some(),
+            max_count: config.max_count,
+        },
+        printer,
+        sink: if config.json {
+            Box::new(JsonSink { pattern: &pattern })
+        } else {
+            Box::new(printer)
+        },
+        mode: if config.quiet {
+            OutputMode::Quiet
+        } else if config.files_with_matches {
+            OutputMode::FilesWithMatches
+        } else if config.files_without_match {
+            OutputMode::FilesWithoutMatch
+        } else if config.count {
+            OutputMode::Count
+        } else {
+            OutputMode::Lines
+        },
+        binary_files: if config.text {
+            BinaryFiles::Text
+        } else {
+            config.binary_files
+        },
+        search_zip: config.search_zip,
+    };
+    let threads = match config.threads {
+        Some(threads) => threads.max(1),
+        None => thread::available_parallelism().map_or(1, |n| n.get()),
+    };
+    // A terminal sees every line as it is found, anything else gets blocks
+    let mut out: Box<dyn Write> = if io::stdout().is_terminal() {
+        Box::new(LineWriter::new(io::stdout().lock()))
+    } else {
+        Box::new(BufWriter::new(io::stdout().lock()))
+    };
+    // A single file is streamed straight to stdout, several are searched in parallel
+    let summary = if threads == 1 || files.len() == 1 && !Path::new(&files[0]).is_dir() {
+        search_sequential(&searcher, find_files(&files, &walk_options), &mut out)?
     } else {
-        config.pattern.0
+        search_parallel(&searcher, &files, &walk_options, threads, &mut out)?
+    };
+    searcher.sink.summary(&mut out, &summary, start.elapsed())?;
+    out.flush()?;
+    Ok(summary.exit_code(searcher.mode == OutputMode::Quiet))
+}
+/// Streams the lines of `reader` matching `pattern` to `out`, the way a single
+/// file is searched by `run`. Exposed for the benchmarks.
+#[doc(hidden)]
+pub fn search_reader<T: BufRead>(
+    reader: T,
+    pattern: &Regex,
+    out: &mut dyn Write,
+) -> GrepResult<usize> {
+    let options = SearchOptions {
+        pattern,
+        invert_match: false,
+        before_context: 0,
+        after_context: 0,
+        context: false,
+        max_count: None,
+    };
+    let printer = Printer {
+        pattern,
+        with_filename: false,
+        line_number: false,
+        byte_offset: false,
+        only_matching: false,
+        color: false,
     };
-    let multiple = entries.len() > 1;
+    find_lines(reader, &options, |line| {
+        Ok(printer.print_line(out, "-", &line)?)
+    })
+}
+fn search_sequential(
+    searcher: &Searcher,
+    entries: impl Iterator<Item = Result<String, String>>,
+    out: &mut dyn Write,
+) -> GrepResult<Summary> {
+    let mut summary = Summary::default();
     for entry in entries {
-        match entry {
-            Err(e) => eprintln!("{}", e),
-            Ok(filename) => match open(&filename) {
-                Err(e) => eprintln!("{}: {}", filename, e),
-                Ok(file) => {
-                    let prefix = if multiple {
-                        format!("{filename}:")
-                    } else {
-                        "".to_string()
-                    };
-                    if config.count {
-                        find_lines(file, &pattern, config.invert_match)
-                            .into_iter()
-                            .for_each(|line| println!("{}{}", prefix, line.iter().count()));
-                    } else {
-                        find_lines(file, &pattern, config.invert_match)
-                            .into_iter()
-                            .flatten()
-                            .for_each(|line| println!("{}{}", prefix, line));
-                    };
+        summary.add(&searcher.search(entry, out, summary.printed));
+        if searcher.mode == OutputMode::Quiet && summary.matched() {
+            break;
+        }
+    }
+    Ok(summary)
+}
+/// The walk runs on its own thread and feeds `threads` searchers, each file's
+/// output is buffered and written in walk order so files never interleave.
+/// The walk gets at most a few files per thread ahead of the output, so only
+/// their output is held in memory at a time. That still means all matches of
+/// one file are kept until it is written, `-j 1` streams them instead.
+fn search_parallel(
+    searcher: &Searcher,
+    files: &[String],
+    walk_options: &WalkOptions,
+    threads: usize,
+    out: &mut dyn Write,
+) -> GrepResult<Summary> {
+    type Found = (Vec<u8>, FileResult);
+    let quit = AtomicBool::new(false);
+    let (job_tx, job_rx) =
+        mpsc::sync_channel::<(Result<String, String>, SyncSender<Found>)>(threads * 4);
+    // Every file gets its own result channel, queued in walk order
+    let (order_tx, order_rx) = mpsc::sync_channel::<Receiver<Found>>(threads * 4);
+    let job_rx = Mutex::new(job_rx);
+    thread::scope(|scope| {
+        scope.spawn(|| {
+            for entry in find_files(files, walk_options) {
+                let (result_tx, result_rx) = mpsc::sync_channel(1);
+                if quit.load(Ordering::Relaxed)
+                    || order_tx.send(result_rx).is_err()
+                    || job_tx.send((entry, result_tx)).is_err()
+                {
+                    break;
                 }
-            },
+            }
+            drop(order_tx);
+            drop(job_tx);
+        });
+        for _ in 0..threads {
+            let (job_rx, quit) = (&job_rx, &quit);
+            scope.spawn(move || loop {
+                let job = job_rx.lock().unwrap().recv();
+                let Ok((entry, result_tx)) = job else { break };
+                // Keep taking jobs so the walk is never stuck sending one
+                if quit.load(Ordering::Relaxed) {
+                    continue;
+                }
+                let mut output = Vec::new();
+                let result = searcher.search(entry, &mut output, false);
+                // Nobody is waiting for it once the output has stopped
+                let _ = result_tx.send((output, result));
+            });
+        }
+        let mut summary = Summary::default();
+        let written = (|| -> GrepResult<()> {
+            for result_rx in &order_rx {
+                let Ok((output, result)) = result_rx.recv() else {
+                    break;
+                };
+                // Hunks from different files are separated too
+                let separate = searcher.mode == OutputMode::Lines && searcher.options.has_context();
+                if separate && summary.printed && result.printed {
+                    searcher.sink.line(out, "", &Line::Break)?;
+                }
+                out.write_all(&output)?;
+                summary.add(&result);
+                if searcher.mode == OutputMode::Quiet && summary.matched() {
+                    break;
+                }
+            }
+            Ok(())
+        })();
+        // Stops the walk and the searchers, also when writing failed
+        quit.store(true, Ordering::Relaxed);
+        drop(order_rx);
+        written.map(|_| summary)
+    })
+}
+/// `auto` colors only when writing to a terminal and `NO_COLOR` is unset
+fn use_color(choice: ColorChoice) -> bool {
+    match choice {
+        ColorChoice::Always => true,
+        ColorChoice::Never => false,
+        ColorChoice::Auto => {
+            env::var_os("NO_COLOR").is_none_or(|value| value.is_empty())
+                && io::stdout().is_terminal()
         }
     }
-    Ok(())
 }
 pub fn get_args() -> GrepResult<Config> {
     Ok(Config::parse())
 }
-fn parse_regex(pattern: &str) -> Result<(Regex, InsensitiveRegex), RegexError> {
-    // I can't find an easy way to pass the insensitive param to this function
-    Ok((
-        Regex::new(pattern)?,
-        RegexBuilder::new(pattern).case_insensitive(true).build()?,
-    ))
-}
-fn find_lines<T: BufRead>(file: T, pattern: &Regex, invert_match: bool) -> GrepResult<Vec<String>> {
-    let result = file
-        .lines()
-        .flat_map(|line| {
-            if pattern.is_match(line.as_ref().unwrap()) ^ invert_match {
-                Some(line.unwrap())
+/// Compiles all -e, -f and positional patterns into a single regex.
+/// Every pattern is checked on its own first so one can't break out of its
+/// group, then they are joined as alternatives, which the regex engine turns
+/// into a literal (Aho-Corasick) search when they are fixed strings.
+fn build_pattern(config: &Config) -> GrepResult<Regex> {
+    let mut patterns = config.regexp.clone();
+    for filename in &config.pattern_files {
+        let contents = fs::read_to_string(filename).map_err(|e| format!("{}: {}", filename, e))?;
+        patterns.extend(contents.lines().map(String::from));
+    }
+    if config.regexp.is_empty() && config.pattern_files.is_empty() {
+        patterns.extend(config.pattern.clone());
+    }
+    let alternatives = patterns
+        .iter()
+        .map(|pattern| {
+            if config.fixed_strings {
+                Ok(regex::escape(pattern))
             } else {
-                None
+                regex_syntax::Parser::new()
+                    .parse(pattern)
+                    .map(|_| format!("(?:{})", pattern))
+                    .map_err(|e| format!("invalid value '{}': {}", pattern, e))
             }
         })
-        .collect::<Vec<_>>();
-    Ok(result)
-}
-fn find_files(paths: &[String], recursive: bool) -> Vec<GrepResult<String>> {
-    let mut result = Vec::new();
-    paths.iter().for_each(|path| {
-        if path == "-" {
-            result.push(Ok("-".into()));
-            return;
-        };
-        let path_struct = Path::new(path);
-        if !path_struct.exists() {
-            result.push(Err(format!("{path}: Path does not exist").into()));
-            return;
+        .collect::<Result<Vec<_>, _>>()?;
+    // An empty list of patterns (e.g. an empty -f file) matches nothing
+    let mut combined = if alternatives.is_empty() {
+        r"[^\s\S]".to_string()
+    } else {
+        alternatives.join("|")
+    };
+    if config.word_regexp {
+        combined = format!(r"\b{{start-half}}(?:{})\b{{end-half}}", combined);
+    }
+    if config.line_regexp {
+        combined = format!("^(?:{})$", combined);
+    }
+    Ok(RegexBuilder::new(&combined)
+        .case_insensitive(config.insensitive)
+        .build()?)
+}
+/// Streams `file` line by line, passing matches and their context to `emit`.
+/// Only the last `before_context` lines are kept around, so memory use does
+/// not grow with the size of the input. Returns the number of matching lines.
+fn find_lines<T: BufRead>(
+    mut file: T,
+    options: &SearchOptions,
+    mut emit: impl FnMut(Line) -> GrepResult<()>,
+) -> GrepResult<usize> {
+    let has_context = options.has_context();
+    let mut before: VecDeque<LineInfo> = VecDeque::with_capacity(options.before_context);
+    let mut after_left = 0;
+    let mut last_emitted: Option<usize> = None;
+    let mut matches = 0;
+    let mut number = 0;
+    let mut offset = 0;
+    let mut buf = Vec::new();
+    loop {
+        buf.clear();
+        let bytes_read = file.read_until(b'\n', &mut buf)?;
+        if bytes_read == 0 {
+            break;
+        }
+        number += 1;
+        if buf.ends_with(b"\n") {
+            buf.pop();
+        }
+        // Once max_count is reached further matches can only be trailing context
+        let is_match = options.pattern.is_match(strip_cr(&buf)) ^ options.invert_match
+            && options.max_count.is_none_or(|max| matches < max);
+        let line_offset = offset;
+        let mut info = || LineInfo {
+            number,
+            offset: line_offset,
+            text: std::mem::take(&mut buf),
         };
-        if recursive {
-            if path_struct.is_dir() {
-                WalkDir::new(path).into_iter().for_each(|p| match p {
-                    Err(_e) => result.push(Err("walkdir error".into())),
-                    Ok(p) => {
-                        if Path::new(p.path()).is_file() {
-                            result.push(Ok(p.path().display().to_string()))
-                        }
-                    }
-                });
-            } else {
-                result.push(Err(format!("{path} is a directory").into()))
+        offset += bytes_read;
+        if is_match {
+            matches += 1;
+            let first = before.front().map_or(number, |info| info.number);
+            if has_context && matches!(last_emitted, Some(last) if first > last + 1) {
+                emit(Line::Break)?;
             }
-        } else if path_struct.is_file() {
-            result.push(Ok(path.clone()))
-        } else {
-            result.push(Err(format!("{path} is a directory").into()))
+            for info in before.drain(..) {
+                emit(Line::Context(info))?;
+            }
+            emit(Line::Match(info()))?;
+            last_emitted = Some(number);
+            after_left = options.after_context;
+        } else if after_left > 0 {
+            emit(Line::Context(info()))?;
+            last_emitted = Some(number);
+            after_left -= 1;
+        } else if options.before_context > 0 {
+            if before.len() == options.before_context {
+                before.pop_front();
+            }
+            before.push_back(info());
         }
-    });
-    result
+        if after_left == 0 && options.max_count == Some(matches) {
+            break;
+        }
+    }
+    Ok(matches)
+}
+/// CRLF lines are printed with their `\r` like grep does, but it is not
+/// part of what the pattern sees, so `$` and `-x` still match them
+fn strip_cr(text: &[u8]) -> &[u8] {
+    text.strip_suffix(b"\r").unwrap_or(text)
+}
+#[derive(Default)]
+struct WalkOptions {
+    recursive: bool,
+    no_ignore: bool,
+    include: Option<GlobSet>,
+    exclude: Option<GlobSet>,
+    exclude_dir: Option<GlobSet>,
+}
+impl WalkOptions {
+    /// --include and --exclude are matched against the file name only
+    fn wants_file(&self, path: &Path) -> bool {
+        let name = path.file_name().unwrap_or(path.as_os_str());
+        self.include
+            .as_ref()
+            .is_none_or(|globs| globs.is_match(name))
+            && !self
+                .exclude
+                .as_ref()
+                .is_some_and(|globs| globs.is_match(name))
+    }
 }
-fn open(filename: &str) -> GrepResult<Box<dyn BufRead>> {
-    match filename {
-        "-" => Ok(Box::new(BufReader::new(io::stdin()))),
-        _ => Ok(Box::new(BufReader::new(File::open(filename)?))),
+fn build_globs(globs: &[String]) -> GrepResult<Option<GlobSet>> {
+    if globs.is_empty() {
+        return Ok(None);
     }
+    let mut builder = GlobSetBuilder::new();
+    for glob in globs {
+        builder.add(Glob::new(glob)?);
+    }
+    Ok(Some(builder.build()?))
+}
+/// Lazily yields the files to search, walking directories in a stable order
+/// with .gitignore and .ignore rules applied unless `no_ignore` is set.
+fn find_files<'a>(
+    paths: &'a [String],
+    options: &'a WalkOptions,
+) -> impl Iterator<Item = Result<String, String>> + 'a {
+    paths.iter().flat_map(move |path| {
+        let found: Box<dyn Iterator<Item = Result<String, String>>> = if path == "-" {
+            Box::new(iter::once(Ok("-".into())))
+        } else if !Path::new(path).exists() {
+            Box::new(iter::once(Err(format!("{path}: Path does not exist"))))
+        } else if !Path::new(path).is_dir() {
+            Box::new(
+                options
+                    .wants_file(Path::new(path))
+                    .then(|| Ok(path.clone()))
+                    .into_iter(),
+            )
+        } else if !options.recursive {
+            Box::new(iter::once(Err(format!("{path} is a directory"))))
+        } else {
+            Box::new(walk(path, options))
+        };
+        found
+    })
+}
+fn walk<'a>(
+    path: &str,
+    options: &'a WalkOptions,
+) -> impl Iterator<Item = Result<String, String>> + 'a {
+    let exclude_dir = options.exclude_dir.clone();
+    WalkBuilder::new(path)
+        .standard_filters(!options.no_ignore)
+        // Like grep, search hidden files, only version control data is skipped
+        .hidden(false)
+        .filter_entry(move |entry| {
+            let is_dir = entry.file_type().is_some_and(|t| t.is_dir());
+            let excluded = entry.depth() > 0
+                && is_dir
+                && exclude_dir
+                    .as_ref()
+                    .is_some_and(|globs| globs.is_match(entry.file_name()));
+            entry.file_name() != ".git" && !excluded
+        })
+        .sort_by_file_name(|a, b| a.cmp(b))
+        .build()
+        .filter_map(move |entry| match entry {
+            Err(e) => Some(Err(e.to_string())),
+            Ok(entry) => {
+                let is_file = entry.file_type().is_some_and(|t| t.is_file());
+                (is_file && options.wants_file(entry.path()))
+                    .then(|| Ok(entry.path().display().to_string()))
+            }
+        })
+}
+fn open(filename: &str, search_zip: bool) -> GrepResult<Box<dyn BufRead>> {
+    let file: Box<dyn BufRead> = match filename {
+        "-" => Box::new(BufReader::new(io::stdin())),
+        _ => Box::new(BufReader::new(File::open(filename)?)),
+    };
+    if search_zip {
+        decompress(file)
+    } else {
+        Ok(file)
+    }
+}
+/// Wraps `file` in the decompressor its magic bytes call for, anything that
+/// is not compressed is returned as it is.
+fn decompress(mut file: Box<dyn BufRead>) -> GrepResult<Box<dyn BufRead>> {
+    let magic = file.fill_buf()?;
+    let decoder: Box<dyn Read> = if magic.starts_with(&[0x1f, 0x8b]) {
+        Box::new(MultiGzDecoder::new(file))
+    } else if magic.starts_with(b"BZh") {
+        Box::new(MultiBzDecoder::new(file))
+    } else if magic.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+        Box::new(XzDecoder::new_multi_decoder(file))
+    } else if magic.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+        Box::new(zstd::Decoder::with_buffer(file)?)
+    } else {
+        return Ok(file);
+    };
+    Ok(Box::new(BufReader::new(decoder)))
 }
 #[cfg(test)]
 mod test {
-    use super::{find_files, find_lines};
+    use super::{
+        build_globs, build_pattern, find_files, find_lines, json_data, Config, GrepResult,
+        JsonSink, Line, LineInfo, Printer, SearchOptions, WalkOptions,
+    };
+    use clap::Parser;
     use rand::{distributions::Alphanumeric, Rng};
-    use regex::{Regex, RegexBuilder};
+    use regex::bytes::{Regex, RegexBuilder};
+    use serde_json::json;
+    use std::fs;
     use std::io::Cursor;
+    fn collect_lines(
+        text: &[u8],
+        pattern: &Regex,
+        invert_match: bool,
+        before_context: usize,
+        after_context: usize,
+    ) -> GrepResult<Vec<Line>> {
+        let options = SearchOptions {
+            pattern,
+            invert_match,
+            before_context,
+            after_context,
+            context: before_context > 0 || after_context > 0,
+            max_count: None,
+        };
+        let mut lines = Vec::new();
+        find_lines(Cursor::new(text), &options, |line| {
+            lines.push(line);
+            Ok(())
+        })?;
+        Ok(lines)
+    }
+    fn info(number: usize, offset: usize, text: &str) -> LineInfo {
+        LineInfo {
+            number,
+            offset,
+            text: text.as_bytes().to_vec(),
+        }
+    }
     #[test]
     fn test_find_lines_standard() {
         // The pattern _or_ should match the one line, "Lorem"
         let text = b"Lorem\nIpsum\r\nDOLOR";
         let re1 = Regex::new("or").unwrap();
-        let matches = find_lines(Cursor::new(&text), &re1, false);
+        let matches = collect_lines(text, &re1, false, 0, 0);
         assert!(matches.is_ok());
         assert_eq!(matches.unwrap().len(), 1);
     }
@@ -149,7 +1046,7 @@ mod test {
         // When inverted, the function should match the other two lines
         let text = b"Lorem\nIpsum\r\nDOLOR";
         let re1 = Regex::new("or").unwrap();
-        let matches = find_lines(Cursor::new(&text), &re1, true);
+        let matches = collect_lines(text, &re1, true, 0, 0);
         assert!(matches.is_ok());
         assert_eq!(matches.unwrap().len(), 2);
     }
@@ -162,7 +1059,7 @@ mod test {
             .build()
             .unwrap();
         // The two lines "Lorem" and "DOLOR" should match
-        let matches = find_lines(Cursor::new(&text), &re2, false);
+        let matches = collect_lines(text, &re2, false, 0, 0);
         assert!(matches.is_ok());
         assert_eq!(matches.unwrap().len(), 2);
     }
@@ -174,21 +1071,190 @@ mod test {
             .case_insensitive(true)
             .build()
             .unwrap();
-        let matches = find_lines(Cursor::new(&text), &re2, true);
+        let matches = collect_lines(text, &re2, true, 0, 0);
         assert!(matches.is_ok());
         assert_eq!(matches.unwrap().len(), 1);
     }
     #[test]
+    fn test_find_lines_crlf() {
+        // The \r of a CRLF line is kept but the pattern doesn't see it
+        let text = b"Lorem\r\nIpsum\r\n";
+        let re = Regex::new("^Lorem$").unwrap();
+        let lines = collect_lines(text, &re, false, 0, 0).unwrap();
+        assert_eq!(lines, vec![Line::Match(info(1, 0, "Lorem\r"))]);
+    }
+    #[test]
+    fn test_find_lines_context() {
+        // Context lines surround the match, non-adjacent hunks get a separator
+        let text = b"a\nb\nmatch\nc\nd\ne\nf\nmatch\ng";
+        let re = Regex::new("match").unwrap();
+        let lines = collect_lines(text, &re, false, 1, 1).unwrap();
+        assert_eq!(
+            lines,
+            vec![
+                Line::Context(info(2, 2, "b")),
+                Line::Match(info(3, 4, "match")),
+                Line::Context(info(4, 10, "c")),
+                Line::Break,
+                Line::Context(info(7, 16, "f")),
+                Line::Match(info(8, 18, "match")),
+                Line::Context(info(9, 24, "g")),
+            ]
+        );
+    }
+    #[test]
+    fn test_find_lines_context_overlapping() {
+        // Overlapping context is printed once and without a separator
+        let text = b"a\nmatch\nb\nmatch\nc";
+        let re = Regex::new("match").unwrap();
+        let lines = collect_lines(text, &re, false, 2, 2).unwrap();
+        assert_eq!(lines.len(), 5);
+        assert!(!lines.contains(&Line::Break));
+    }
+    #[test]
+    fn test_find_lines_max_count() {
+        // Reading stops at max_count matches, after any trailing context
+        let text = b"match\na\nmatch\nb\nc";
+        let re = Regex::new("match").unwrap();
+        let options = SearchOptions {
+            pattern: &re,
+            invert_match: false,
+            before_context: 0,
+            after_context: 1,
+            context: true,
+            max_count: Some(1),
+        };
+        let mut lines = Vec::new();
+        let matches = find_lines(Cursor::new(&text), &options, |line| {
+            lines.push(line);
+            Ok(())
+        });
+        assert_eq!(matches.unwrap(), 1);
+        assert_eq!(
+            lines,
+            vec![
+                Line::Match(info(1, 0, "match")),
+                Line::Context(info(2, 6, "a"))
+            ]
+        );
+    }
+    fn pattern_from(args: &[&str]) -> GrepResult<Regex> {
+        build_pattern(&Config::try_parse_from(args)?)
+    }
+    #[test]
+    fn test_build_pattern_multiple() {
+        // Each -e pattern is an alternative, the positional is then a file
+        let re = pattern_from(&["grepr", "-e", "fox", "-e", "dog", "cat"]).unwrap();
+        assert!(re.is_match(b"a fox"));
+        assert!(re.is_match(b"a dog"));
+        assert!(!re.is_match(b"a cat"));
+    }
+    #[test]
+    fn test_build_pattern_isolates_patterns() {
+        // A pattern can't close the group of its neighbour
+        assert!(pattern_from(&["grepr", "-e", "a)", "-e", "(b"]).is_err());
+        assert!(pattern_from(&["grepr", "*foo"]).is_err());
+    }
+    #[test]
+    fn test_build_pattern_fixed_strings() {
+        let re = pattern_from(&["grepr", "-F", "-e", "a.c", "-e", "*"]).unwrap();
+        assert!(re.is_match(b"a.c"));
+        assert!(!re.is_match(b"abc"));
+        assert!(re.is_match(b"2 * 3"));
+    }
+    #[test]
+    fn test_build_pattern_word_and_line() {
+        let re = pattern_from(&["grepr", "-w", "-i", "the"]).unwrap();
+        assert!(re.is_match(b"The fox"));
+        assert!(!re.is_match(b"them"));
+        let re = pattern_from(&["grepr", "-x", "-e", "fox", "-e", "dog"]).unwrap();
+        assert!(re.is_match(b"dog"));
+        assert!(!re.is_match(b"lazy dog"));
+    }
+    #[test]
+    fn test_printer_prefix() {
+        // Filename, line number and byte offset are joined in that order
+        let re = Regex::new("or").unwrap();
+        let mut printer = Printer {
+            pattern: &re,
+            with_filename: false,
+            line_number: false,
+            byte_offset: false,
+            only_matching: false,
+            color: false,
+        };
+        assert_eq!(printer.prefix("fox.txt", 3, 42, ':'), "");
+        printer.with_filename = true;
+        assert_eq!(printer.prefix("fox.txt", 3, 42, ':'), "fox.txt:");
+        printer.line_number = true;
+        assert_eq!(printer.prefix("fox.txt", 3, 42, '-'), "fox.txt-3-");
+        printer.byte_offset = true;
+        assert_eq!(printer.prefix("fox.txt", 3, 42, ':'), "fox.txt:3:42:");
+    }
+    #[test]
+    fn test_printer_highlight() {
+        // Every non-empty match is wrapped in the match color
+        let re = Regex::new("o*r").unwrap();
+        let mut printer = Printer {
+            pattern: &re,
+            with_filename: false,
+            line_number: false,
+            byte_offset: false,
+            only_matching: false,
+            color: false,
+        };
+        assert_eq!(printer.highlight(b"Lorem dolor"), b"Lorem dolor");
+        printer.color = true;
+        assert_eq!(
+            printer.highlight(b"Lorem dolor"),
+            b"L\x1b[01;31mor\x1b[0mem dol\x1b[01;31mor\x1b[0m"
+        );
+    }
+    #[test]
+    fn test_json_line_event() {
+        let re = Regex::new("o*r").unwrap();
+        let sink = JsonSink { pattern: &re };
+        assert_eq!(
+            sink.line_event("a.txt", &info(2, 8, "Lorem dolor"), true),
+            json!({
+                "path": { "text": "a.txt" },
+                "lines": { "text": "Lorem dolor\n" },
+                "line_number": 2,
+                "absolute_offset": 8,
+                "submatches": [
+                    { "match": { "text": "or" }, "start": 1, "end": 3 },
+                    { "match": { "text": "or" }, "start": 9, "end": 11 },
+                ],
+            })
+        );
+        assert_eq!(
+            sink.line_event("a.txt", &info(2, 8, "Lorem dolor"), false)["submatches"],
+            json!([])
+        );
+    }
+    #[test]
+    fn test_json_data() {
+        assert_eq!(json_data(b"caf\xc3\xa9"), json!({ "text": "café" }));
+        assert_eq!(json_data(b"caf\xe9"), json!({ "bytes": "Y2Fm6Q==" }));
+    }
+    fn files_in(paths: &[String], recursive: bool) -> Vec<Result<String, String>> {
+        let options = WalkOptions {
+            recursive,
+            ..Default::default()
+        };
+        find_files(paths, &options).collect()
+    }
+    #[test]
     fn test_find_file_that_exists() {
         // Verify that the function finds a file known to exist
-        let files = find_files(&["./tests/inputs/fox.txt".to_string()], false);
+        let files = files_in(&["./tests/inputs/fox.txt".to_string()], false);
         assert_eq!(files.len(), 1);
         assert_eq!(files[0].as_ref().unwrap(), "./tests/inputs/fox.txt");
     }
     #[test]
     fn test_find_files_rejects_directory_without_recursive_option() {
         // The function should reject a directory without the recursive option
-        let files = find_files(&["./tests/inputs".to_string()], false);
+        let files = files_in(&["./tests/inputs".to_string()], false);
         assert_eq!(files.len(), 1);
         if let Err(e) = &files[0] {
             assert_eq!(e.to_string(), "./tests/inputs is a directory");
@@ -196,20 +1262,26 @@ mod test {
     }
     #[test]
     fn test_find_files_with_recursive_option() {
-        // Verify the function recurses to find four files in the directory
-        let res = find_files(&["./tests/inputs".to_string()], true);
+        // Verify the function recurses to find ten files in the directory
+        let res = files_in(&["./tests/inputs".to_string()], true);
         let mut files: Vec<String> = res
             .iter()
             .map(|r| r.as_ref().unwrap().replace('\\', "/"))
             .collect();
         files.sort();
-        assert_eq!(files.len(), 4);
+        assert_eq!(files.len(), 10);
         assert_eq!(
             files,
             vec![
+                "./tests/inputs/binary.dat",
                 "./tests/inputs/bustle.txt",
+                "./tests/inputs/bustle.txt.bz2",
+                "./tests/inputs/bustle.txt.gz",
+                "./tests/inputs/bustle.txt.xz",
+                "./tests/inputs/bustle.txt.zst",
                 "./tests/inputs/empty.txt",
                 "./tests/inputs/fox.txt",
+                "./tests/inputs/latin1.txt",
                 "./tests/inputs/nobody.txt",
             ]
         );
@@ -223,8 +1295,49 @@ mod test {
             .map(char::from)
             .collect();
         // Verify that the function returns the bad file as an error
-        let files = find_files(&[bad], false);
+        let files = files_in(&[bad], false);
         assert_eq!(files.len(), 1);
         assert!(files[0].is_err());
     }
+    #[test]
+    fn test_find_files_globs() {
+        // --include and --exclude look at file names, --exclude-dir at directories
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("src/target")).unwrap();
+        for file in ["a.rs", "b.txt", "src/c.rs", "src/target/d.rs"] {
+            fs::write(dir.path().join(file), "").unwrap();
+        }
+        let root = dir.path().display().to_string();
+        let options = WalkOptions {
+            recursive: true,
+            include: build_globs(&["*.rs".to_string()]).unwrap(),
+            exclude: build_globs(&["a.*".to_string()]).unwrap(),
+            exclude_dir: build_globs(&["target".to_string()]).unwrap(),
+            ..Default::default()
+        };
+        let paths = vec![root.clone()];
+        let files: Vec<_> = find_files(&paths, &options).map(|f| f.unwrap()).collect();
+        assert_eq!(files, vec![format!("{root}/src/c.rs")]);
+    }
+    #[test]
+    fn test_find_files_respects_ignore_files() {
+        // Files listed in .ignore are skipped unless no_ignore is set
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join(".ignore"), "skipped.txt\n").unwrap();
+        fs::write(dir.path().join("kept.txt"), "").unwrap();
+        fs::write(dir.path().join("skipped.txt"), "").unwrap();
+        let root = dir.path().display().to_string();
+        let mut options = WalkOptions {
+            recursive: true,
+            ..Default::default()
+        };
+        let paths = vec![root.clone()];
+        let files: Vec<_> = find_files(&paths, &options).map(|f| f.unwrap()).collect();
+        assert_eq!(
+            files,
+            vec![format!("{root}/.ignore"), format!("{root}/kept.txt")]
+        );
+        options.no_ignore = true;
+        assert_eq!(find_files(&paths, &options).count(), 3);
+    }
 }
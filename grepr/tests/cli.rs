@@ -12,6 +12,8 @@ const EMPTY: &str = "tests/inputs/empty.txt";
 const FOX: &str = "tests/inputs/fox.txt";
 const NOBODY: &str = "tests/inputs/nobody.txt";
 const INPUTS_DIR: &str = "tests/inputs";
+const BINARY: &str = "tests/inputs/binary.dat";
+const LATIN1: &str = "tests/inputs/latin1.txt";
 
 // --------------------------------------------------
 fn gen_bad_file() -> String {
@@ -130,6 +132,24 @@ fn nobody_insensitive() -> TestResult {
     )
 }
 
+// --------------------------------------------------
+#[test]
+fn crlf_lines_keep_their_ending() -> TestResult {
+    // nobody.txt has CRLF line endings, -x and $ match without the \r
+    for args in [
+        &["-x", "Then there's a pair of us!"][..],
+        &["-i", "PAIR OF US!$"],
+    ] {
+        Command::cargo_bin(PRG)?
+            .args(args)
+            .arg(NOBODY)
+            .assert()
+            .success()
+            .stdout("Then there's a pair of us!\r\n");
+    }
+    Ok(())
+}
+
 // --------------------------------------------------
 #[test]
 fn multiple_files() -> TestResult {
@@ -273,3 +293,474 @@ fn stdin_insensitive_count() -> TestResult {
         .stdout(expected);
     Ok(())
 }
+
+// --------------------------------------------------
+#[test]
+fn bustle_after_context() -> TestResult {
+    run(
+        &["-A", "1", "The", BUSTLE],
+        "tests/expected/bustle.txt.the.capitalized.A1",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn bustle_before_context() -> TestResult {
+    run(
+        &["-B", "2", "up", BUSTLE],
+        "tests/expected/bustle.txt.up.B2",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn multiple_files_context() -> TestResult {
+    run(
+        &["-C", "1", "-i", "the", BUSTLE, EMPTY, FOX, NOBODY],
+        "tests/expected/all.the.lowercase.insensitive.C1",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn bustle_line_number() -> TestResult {
+    run(
+        &["-n", "The", BUSTLE],
+        "tests/expected/bustle.txt.the.capitalized.n",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn bustle_line_number_byte_offset_context() -> TestResult {
+    run(
+        &["-nb", "-C", "1", "up", BUSTLE],
+        "tests/expected/bustle.txt.up.nb.C1",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn bustle_only_matching_byte_offset() -> TestResult {
+    run(
+        &["-ob", "The", BUSTLE],
+        "tests/expected/bustle.txt.the.capitalized.ob",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn multiple_files_only_matching_line_number() -> TestResult {
+    run(
+        &["-on", "-i", "the", BUSTLE, EMPTY, FOX, NOBODY],
+        "tests/expected/all.the.lowercase.insensitive.on",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn color_always() -> TestResult {
+    run(
+        &["--color=always", "-n", "-i", "the", BUSTLE, FOX],
+        "tests/expected/bustle.fox.the.insensitive.n.color",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn color_auto_not_a_terminal() -> TestResult {
+    // Output is captured by the test, so auto must not color it
+    run(
+        &["--color=auto", "The", BUSTLE],
+        "tests/expected/bustle.txt.the.capitalized",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_bad_color() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--color=sometimes", "The", BUSTLE])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("invalid value 'sometimes'"));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn files_with_matches() -> TestResult {
+    run(
+        &["-l", "-i", "the", BUSTLE, EMPTY, FOX, NOBODY],
+        "tests/expected/all.the.lowercase.insensitive.l",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn files_without_match() -> TestResult {
+    run(
+        &["-L", "-i", "the", BUSTLE, EMPTY, FOX, NOBODY],
+        "tests/expected/all.the.lowercase.insensitive.L",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn quiet_match() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-q", "fox", FOX, BUSTLE])
+        .assert()
+        .code(0)
+        .stdout("");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn quiet_match_ignores_later_bad_file() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-q", "fox", FOX, &gen_bad_file()])
+        .assert()
+        .code(0)
+        .stdout("")
+        .stderr("");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn exit_code_no_match() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["nobody", BUSTLE])
+        .assert()
+        .code(1)
+        .stdout("");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn exit_code_bad_file() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["fox", FOX, &gen_bad_file()])
+        .assert()
+        .code(2)
+        .stdout(predicate::str::contains("fox"));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn multiple_regexp() -> TestResult {
+    run(
+        &["-e", "fox", "-e", "bustle", BUSTLE, FOX],
+        "tests/expected/bustle.fox.e.fox.e.bustle",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn pattern_file() -> TestResult {
+    run(
+        &["-f", "tests/patterns.txt", BUSTLE, EMPTY, FOX, NOBODY],
+        "tests/expected/all.patterns",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn word_regexp_insensitive() -> TestResult {
+    run(
+        &["-w", "-i", "the", BUSTLE],
+        "tests/expected/bustle.txt.the.w.insensitive",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn zero_context_separates_hunks() -> TestResult {
+    for flag in ["-C", "-A", "-B"] {
+        Command::cargo_bin(PRG)?
+            .args([flag, "0", "a"])
+            .write_stdin("a\na\nb\na\n")
+            .assert()
+            .success()
+            .stdout("a\na\n--\na\n");
+    }
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn word_regexp_punctuation() -> TestResult {
+    // Only the outer side of leading or trailing punctuation needs a boundary
+    for pattern in ["@foo", "foo!"] {
+        Command::cargo_bin(PRG)?
+            .args(["-w", pattern])
+            .write_stdin("x @foo y\nx@foo\nsay foo! now\nfoo!x\n")
+            .assert()
+            .success()
+            .stdout(if pattern == "@foo" {
+                "x @foo y\n"
+            } else {
+                "say foo! now\n"
+            });
+    }
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn fixed_strings_line_regexp() -> TestResult {
+    run(
+        &["-F", "-x", "Until eternity.", BUSTLE],
+        "tests/expected/bustle.txt.x.F",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_bad_pattern_file() -> TestResult {
+    let bad = gen_bad_file();
+    Command::cargo_bin(PRG)?
+        .args(["-f", &bad, FOX])
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains(bad));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn recursive_include() -> TestResult {
+    run(
+        &[
+            "-ri",
+            "--include",
+            "b*",
+            "--include",
+            "n*",
+            "the",
+            INPUTS_DIR,
+        ],
+        "tests/expected/the.recursive.insensitive.include",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn recursive_exclude() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-r", "--exclude", "*.txt", "dog", INPUTS_DIR])
+        .assert()
+        .code(1)
+        .stdout("");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn recursive_exclude_dir() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-rl", "--exclude-dir", "expected", "dog", "tests"])
+        .assert()
+        .stdout("tests/cli.rs\ntests/inputs/fox.txt\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn recursive_single_thread() -> TestResult {
+    run(
+        &["-ri", "-j", "1", "then", INPUTS_DIR],
+        "tests/expected/the.recursive.insensitive",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn binary_file_matches() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["fox", BINARY])
+        .assert()
+        .code(0)
+        .stdout("Binary file tests/inputs/binary.dat matches\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn binary_file_count() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-c", "fox", BINARY])
+        .assert()
+        .stdout("2\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn binary_file_as_text() -> TestResult {
+    let expected = fs::read("tests/expected/binary.dat.fox.a")?;
+    for flag in ["-a", "--binary-files=text"] {
+        Command::cargo_bin(PRG)?
+            .args([flag, "fox", BINARY])
+            .assert()
+            .stdout(expected.clone());
+    }
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn binary_file_without_match() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--binary-files=without-match", "fox", BINARY, FOX])
+        .assert()
+        .code(0)
+        .stdout("tests/inputs/fox.txt:The quick brown fox jumps over the lazy dog.\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn invalid_utf8_is_searched() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["br", LATIN1])
+        .assert()
+        .code(0)
+        .stdout(fs::read("tests/expected/latin1.txt.br")?);
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn max_count() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-m", "1", "The", BUSTLE])
+        .assert()
+        .code(0)
+        .stdout("The bustle in a house\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn max_count_prints_trailing_context() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--max-count=1", "-A", "1", "The", BUSTLE])
+        .assert()
+        .code(0)
+        .stdout("The bustle in a house\nThe morning after death\n");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn max_count_zero() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-m", "0", "The", BUSTLE])
+        .assert()
+        .code(1)
+        .stdout("");
+    Ok(())
+}
+
+// --------------------------------------------------
+fn json_events(args: &[&str]) -> Result<Vec<serde_json::Value>, Box<dyn std::error::Error>> {
+    let output = Command::cargo_bin(PRG)?.args(args).output()?;
+    let stdout = String::from_utf8(output.stdout)?;
+    Ok(stdout
+        .lines()
+        .map(serde_json::from_str)
+        .collect::<Result<_, _>>()?)
+}
+
+// --------------------------------------------------
+#[test]
+fn json_events_in_order() -> TestResult {
+    let events = json_events(&["--json", "-A", "1", "morning|fox", BUSTLE, EMPTY, FOX])?;
+    let types: Vec<_> = events.iter().map(|e| e["type"].as_str().unwrap()).collect();
+    assert_eq!(
+        types,
+        ["begin", "match", "context", "end", "begin", "match", "end", "summary"]
+    );
+    let data = &events[1]["data"];
+    assert_eq!(data["path"]["text"], BUSTLE);
+    assert_eq!(data["lines"]["text"], "The morning after death\n");
+    assert_eq!(data["line_number"], 2);
+    assert_eq!(data["absolute_offset"], 22);
+    assert_eq!(
+        data["submatches"],
+        serde_json::json!([{ "match": { "text": "morning" }, "start": 4, "end": 11 }])
+    );
+    assert_eq!(events[3]["data"]["stats"]["matched_lines"], 1);
+    let stats = &events[7]["data"]["stats"];
+    assert_eq!(stats["searches"], 3);
+    assert_eq!(stats["searches_with_match"], 2);
+    assert_eq!(stats["matched_lines"], 2);
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn json_context_lines() -> TestResult {
+    let events = json_events(&["--json", "-B", "1", "morning", BUSTLE])?;
+    assert_eq!(events[1]["type"], "context");
+    assert_eq!(
+        events[1]["data"]["lines"]["text"],
+        "The bustle in a house\n"
+    );
+    assert_eq!(events[2]["type"], "match");
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn json_conflicts_with_count() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--json", "-c", "fox", FOX])
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("cannot be used with"));
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn search_zip() -> TestResult {
+    let expected = fs::read_to_string("tests/expected/bustle.txt.the.capitalized")?;
+    for ext in ["gz", "bz2", "xz", "zst"] {
+        let archive = format!("{}.{}", BUSTLE, ext);
+        Command::cargo_bin(PRG)?
+            .args(["-z", "The", &archive])
+            .assert()
+            .code(0)
+            .stdout(expected.clone());
+        Command::cargo_bin(PRG)?
+            .args(["The", &archive])
+            .assert()
+            .code(1)
+            .stdout("");
+    }
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn search_zip_leaves_plain_files_alone() -> TestResult {
+    run(
+        &["--search-zip", "The", BUSTLE],
+        "tests/expected/bustle.txt.the.capitalized",
+    )
+}
+
+// --------------------------------------------------
+#[test]
+fn search_zip_recursive() -> TestResult {
+    run(
+        &["-rz", "-i", "morning", INPUTS_DIR],
+        "tests/expected/inputs.morning.rz",
+    )
+}
@@ -0,0 +1,53 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use std::io::{self, BufRead, BufReader, Cursor, Seek, SeekFrom, Write};
+
+const LINES: usize = 1_000_000;
+
+/// The original implementation: count every line of the file, then read it
+/// again from the start to skip to the last lines.
+fn two_passes(text: &[u8], num_lines: usize, out: &mut impl Write) {
+    let total = Cursor::new(text).lines().count();
+    let mut file = BufReader::new(Cursor::new(text));
+    let mut buf = Vec::new();
+    for line_num in 0.. {
+        if file.read_until(b'\n', &mut buf).unwrap() == 0 {
+            break;
+        }
+        if line_num >= total.saturating_sub(num_lines) {
+            out.write_all(&buf).unwrap();
+        }
+        buf.clear();
+    }
+}
+
+fn reverse_seek(text: &[u8], num_lines: usize, out: &mut impl Write) {
+    let mut file = Cursor::new(text);
+    let start = tailr::last_lines_start(&mut file, num_lines as u64).unwrap();
+    file.seek(SeekFrom::Start(start)).unwrap();
+    io::copy(&mut file, out).unwrap();
+}
+
+fn input() -> Vec<u8> {
+    let mut text = Vec::new();
+    for i in 0..LINES {
+        writeln!(text, "{} request handled in {}ms", i, i % 997).unwrap();
+    }
+    text
+}
+
+fn tail(c: &mut Criterion) {
+    let text = input();
+    let mut group = c.benchmark_group("tail");
+    for num_lines in [10, 10_000] {
+        group.bench_function(format!("two_passes/{}", num_lines), |b| {
+            b.iter(|| two_passes(&text, num_lines, &mut io::sink()))
+        });
+        group.bench_function(format!("reverse_seek/{}", num_lines), |b| {
+            b.iter(|| reverse_seek(&text, num_lines, &mut io::sink()))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, tail);
+criterion_main!(benches);
@@ -1,12 +1,22 @@
 use crate::TakeValue::*;
-use clap::{arg, command, Parser};
+use clap::Parser;
+use notify::{PollWatcher, RecommendedWatcher, RecursiveMode, Watcher};
+use std::collections::VecDeque;
 use std::error::Error;
 use std::fmt::{Formatter, Result as FmtResult};
-use std::fs::File;
-use std::io::{BufRead, BufReader, Read, Seek, SeekFrom};
+use std::fs::{self, File, Metadata};
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::time::Duration;
 
 type TailResult<T> = Result<T, Box<dyn Error>>;
 
+/// How much of a file is read at a time when looking for lines from its end
+const BLOCK_SIZE: usize = 64 * 1024;
+/// How long to wait for a change event before checking followed files anyway
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
 #[derive(Debug, PartialEq, Clone)]
 enum TakeValue {
     PlusZero,
@@ -24,8 +34,8 @@ impl std::fmt::Display for TakeValue {
 #[derive(Parser, Debug)]
 #[command(version, author, about)]
 pub struct Config {
-    /// Input files
-    #[arg(name = "FILES", required = true)]
+    /// Input files, stdin when none or "-" is given
+    #[arg(name = "FILES", default_value = "-")]
     files: Vec<String>,
     /// Number of lines to print
     #[arg(short = 'n', long, default_value_t = TakeNum(10), value_parser=parse_num)]
@@ -33,9 +43,24 @@ pub struct Config {
     /// Number of bytes to print
     #[arg(short = 'c', long, conflicts_with = "lines", value_parser=parse_num)]
     bytes: Option<TakeValue>,
+    /// With -c, start at the next character when the offset splits one
+    #[arg(long, requires = "bytes")]
+    align_chars: bool,
     /// Supress headers
     #[arg(short, long)]
     quiet: bool,
+    /// Always print headers
+    #[arg(short, long, conflicts_with = "quiet")]
+    verbose: bool,
+    /// Output appended data as the file grows
+    #[arg(short, long)]
+    follow: bool,
+    /// Follow the file by name, reopening it after rotation (implies -f)
+    #[arg(short = 'F')]
+    follow_name: bool,
+    /// With -f or -F, stop after process PID exits
+    #[arg(long, value_name = "PID")]
+    pid: Option<i32>,
 }
 fn parse_num(val: &str) -> Result<TakeValue, String> {
     match (val.parse::<i64>(), val.starts_with('+')) {
@@ -48,68 +73,394 @@ fn parse_num(val: &str) -> Result<TakeValue, String> {
 }
 
 pub fn run(config: Config) -> TailResult<()> {
-    let num_files = config.files.len();
+    let following = config.follow || config.follow_name;
+    let mut followed = Vec::new();
+    let mut last_printed = None;
     for (file_num, filename) in config.files.iter().enumerate() {
-        match File::open(&filename) {
-            Err(err) => eprintln!("{}: {}", filename, err),
-            Ok(file) => {
-                if !config.quiet && num_files > 1 {
-                    println!(
-                        "{}==> {} <==",
-                        if file_num > 0 { "\n" } else { "" },
-                        filename
-                    );
+        match open(filename) {
+            Err(err) => {
+                eprintln!("{}: {}", filename, err);
+                if following {
+                    // Wait for it to be created
+                    followed.push(Followed::missing(filename, file_num));
+                }
+            }
+            Ok(input) => {
+                if show_headers(&config) {
+                    print_header(&mut io::stdout(), filename, file_num == 0)?;
+                }
+                last_printed = Some(file_num);
+                match input {
+                    Input::File(file) => {
+                        let total_bytes = file.metadata()?.len() as i64;
+                        let mut file = BufReader::new(file);
+                        let _ = print_file(&mut file, &config);
+                        if following {
+                            let file = file.into_inner();
+                            followed.push(Followed::new(filename, file_num, file, total_bytes)?);
+                        }
+                    }
+                    Input::Stream(stream) => {
+                        let printed = if let Some(bytes) = &config.bytes {
+                            print_bytes(stream, bytes, config.align_chars)
+                        } else {
+                            print_lines(BufReader::new(stream), &config.lines)
+                        };
+                        if let Err(err) = printed {
+                            eprintln!("{}: {}", filename, err);
+                        }
+                    }
                 }
-                let (total_lines, total_bytes) = count_lines_bytes(&filename)?;
-                let file = BufReader::new(file);
-                let _ = if let Some(bytes) = &config.bytes {
-                    print_bytes(file, bytes, total_bytes)
-                } else {
-                    print_lines(file, &config.lines, total_lines)
-                };
             }
         }
     }
+    if following {
+        follow(followed, &config, last_printed)?;
+    }
     Ok(())
 }
-fn count_lines_bytes(filename: &str) -> TailResult<(i64, i64)> {
+fn show_headers(config: &Config) -> bool {
+    !config.quiet && (config.verbose || config.files.len() > 1)
+}
+/// Prints the `==> name <==` line that starts the output of each file
+fn print_header(out: &mut impl Write, filename: &str, first: bool) -> io::Result<()> {
+    writeln!(
+        out,
+        "{}==> {} <==",
+        if first { "" } else { "\n" },
+        if filename == "-" {
+            "standard input"
+        } else {
+            filename
+        }
+    )
+}
+/// An opened input, only regular files can seek back from their end
+enum Input {
+    File(File),
+    Stream(Box<dyn Read>),
+}
+fn open(filename: &str) -> TailResult<Input> {
+    if filename == "-" {
+        return Ok(Input::Stream(Box::new(io::stdin())));
+    }
     let file = File::open(filename)?;
-    let bytes = &file.metadata().unwrap().len();
-    let lines = BufReader::new(file).lines().count();
-    Ok((lines as i64, *bytes as i64))
+    Ok(if file.metadata()?.is_file() {
+        Input::File(file)
+    } else {
+        Input::Stream(Box::new(file))
+    })
+}
+/// A file whose appended data is printed after its tail
+struct Followed {
+    filename: String,
+    file_num: usize,
+    file: Option<File>,
+    id: Option<(u64, u64)>,
+    pos: u64,
+}
+impl Followed {
+    /// Continues after the tail that was printed, or from the size the file
+    /// had when nothing was read
+    fn new(filename: &str, file_num: usize, mut file: File, total_bytes: i64) -> TailResult<Self> {
+        let pos = file.stream_position()?.max(total_bytes as u64);
+        file.seek(SeekFrom::Start(pos))?;
+        Ok(Followed {
+            filename: filename.to_string(),
+            file_num,
+            id: file_id(&file.metadata()?),
+            file: Some(file),
+            pos,
+        })
+    }
+    /// A file that couldn't be opened, it is read from the start once it appears
+    fn missing(filename: &str, file_num: usize) -> Self {
+        Followed {
+            filename: filename.to_string(),
+            file_num,
+            file: None,
+            id: None,
+            pos: 0,
+        }
+    }
+    /// Reads whatever was appended since the last read into `buf`, starting
+    /// over when the file was truncated
+    fn read_new(&mut self, buf: &mut Vec<u8>) -> TailResult<()> {
+        if let Some(file) = &mut self.file {
+            if file.metadata()?.len() < self.pos {
+                eprintln!("{}: file truncated", self.filename);
+                self.pos = file.seek(SeekFrom::Start(0))?;
+            }
+            self.pos += file.read_to_end(buf)? as u64;
+        }
+        Ok(())
+    }
+    /// Switches to a new file created under the same name, after reading
+    /// what was left in the old one
+    fn reopen(&mut self, buf: &mut Vec<u8>) -> TailResult<()> {
+        match fs::metadata(&self.filename) {
+            Err(err) => {
+                if self.file.is_some() {
+                    self.read_new(buf)?;
+                    self.file = None;
+                    eprintln!("{}: has become inaccessible: {}", self.filename, err);
+                }
+            }
+            Ok(meta) if self.file.is_none() || file_id(&meta) != self.id => {
+                self.read_new(buf)?;
+                let file = File::open(&self.filename)?;
+                eprintln!(
+                    "{}: has {}, following new file",
+                    self.filename,
+                    if self.file.is_some() {
+                        "been replaced"
+                    } else {
+                        "appeared"
+                    }
+                );
+                self.id = file_id(&file.metadata()?);
+                self.file = Some(file);
+                self.pos = 0;
+            }
+            Ok(_) => {}
+        }
+        Ok(())
+    }
+}
+/// Prints data appended to the files until interrupted or until `--pid` exits,
+/// with a header whenever the output switches to another file.
+/// Change events wake the loop early, it checks the files every
+/// `POLL_INTERVAL` regardless.
+fn follow(mut files: Vec<Followed>, config: &Config, mut last: Option<usize>) -> TailResult<()> {
+    if files.is_empty() {
+        return Ok(());
+    }
+    let (tx, rx) = mpsc::channel();
+    let mut watcher: Box<dyn Watcher> =
+        match RecommendedWatcher::new(tx.clone(), Default::default()) {
+            Ok(watcher) => Box::new(watcher),
+            Err(_) => Box::new(PollWatcher::new(
+                tx,
+                notify::Config::default().with_poll_interval(POLL_INTERVAL),
+            )?),
+        };
+    for file in &files {
+        // Watch the directory so that renames and new files under the name show up
+        let dir = match Path::new(&file.filename).parent() {
+            Some(dir) if dir != Path::new("") => dir,
+            _ => Path::new("."),
+        };
+        let _ = watcher.watch(dir, RecursiveMode::NonRecursive);
+    }
+    let mut out = io::stdout();
+    let mut buf = Vec::new();
+    loop {
+        // Check before reading so that the last writes of the process are printed
+        let running = config.pid.is_none_or(is_running);
+        for file in &mut files {
+            // Following by descriptor only looks up the name until it is found
+            let result = if config.follow_name || file.file.is_none() {
+                file.reopen(&mut buf).and_then(|_| file.read_new(&mut buf))
+            } else {
+                file.read_new(&mut buf)
+            };
+            if !buf.is_empty() {
+                if show_headers(config) && last != Some(file.file_num) {
+                    print_header(&mut out, &file.filename, last.is_none())?;
+                }
+                last = Some(file.file_num);
+                out.write_all(&buf)?;
+                out.flush()?;
+                buf.clear();
+            }
+            if let Err(err) = result {
+                eprintln!("{}: {}", file.filename, err);
+            }
+        }
+        if !running {
+            return Ok(());
+        }
+        if let Err(RecvTimeoutError::Disconnected) = rx.recv_timeout(POLL_INTERVAL) {
+            std::thread::sleep(POLL_INTERVAL);
+        }
+        while rx.try_recv().is_ok() {}
+    }
+}
+#[cfg(unix)]
+fn file_id(meta: &Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    Some((meta.dev(), meta.ino()))
+}
+#[cfg(not(unix))]
+fn file_id(_meta: &Metadata) -> Option<(u64, u64)> {
+    None
 }
-fn print_bytes<T>(mut file: T, num_bytes: &TakeValue, total_bytes: i64) -> TailResult<()>
+#[cfg(unix)]
+fn is_running(pid: i32) -> bool {
+    // Signal 0 only checks that the process exists
+    let found = unsafe { libc::kill(pid, 0) } == 0;
+    found || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+#[cfg(not(unix))]
+fn is_running(_pid: i32) -> bool {
+    true
+}
+/// Prints the tail of a regular file, seeking straight to where it starts
+/// instead of reading up to it
+fn print_file<T>(file: &mut T, config: &Config) -> TailResult<()>
 where
-    T: Read + Seek,
+    T: BufRead + Seek,
+{
+    let start = match (&config.bytes, &config.lines) {
+        (Some(bytes), _) => {
+            let total_bytes = file.seek(SeekFrom::End(0))?;
+            get_start_index(bytes, total_bytes as i64)
+        }
+        (None, TakeNum(take)) if *take < 0 => Some(last_lines_start(file, take.unsigned_abs())?),
+        (None, lines) => return print_lines(file, lines),
+    };
+    if let Some(start) = start {
+        file.seek(SeekFrom::Start(start))?;
+        if config.bytes.is_some() {
+            print_bytes(file, &PlusZero, config.align_chars)?;
+        } else {
+            print_lines(file, &PlusZero)?;
+        }
+    }
+    Ok(())
+}
+/// Prints from byte `+N` reading forward, or the last `N` bytes kept in a
+/// ring buffer while reading the whole stream
+fn print_bytes<T>(file: T, num_bytes: &TakeValue, align_chars: bool) -> TailResult<()>
+where
+    T: Read,
 {
-    if let Some(start_index) = get_start_index(num_bytes, total_bytes) {
-        file.seek(SeekFrom::Start(start_index))?;
-        let mut buffer = Vec::new();
-        file.read_to_end(&mut buffer)?;
-        if !buffer.is_empty() {
-            print!("{}", String::from_utf8_lossy(&buffer));
+    let mut file = BufReader::new(file);
+    match num_bytes {
+        PlusZero => {}
+        TakeNum(0) => return Ok(()),
+        TakeNum(take) if *take < 0 => {
+            let buffer = last_bytes(&mut file, take.unsigned_abs())?;
+            return copy_bytes(buffer.as_slice(), align_chars);
+        }
+        TakeNum(take) => {
+            io::copy(&mut file.by_ref().take(*take as u64 - 1), &mut io::sink())?;
         }
     }
+    copy_bytes(file, align_chars)
+}
+/// Writes the rest of `file` to stdout untouched, so binary data passes through
+fn copy_bytes(mut file: impl BufRead, align_chars: bool) -> TailResult<()> {
+    if align_chars {
+        align_to_char(&mut file)?;
+    }
+    io::copy(&mut file, &mut io::stdout().lock())?;
     Ok(())
 }
-fn print_lines(mut file: impl BufRead, num_lines: &TakeValue, total_lines: i64) -> TailResult<()> {
-    if let Some(start) = get_start_index(num_lines, total_lines) {
-        let mut line_num = 0;
-        let mut buf = Vec::new();
-        loop {
-            let bytes_read = file.read_until(b'\n', &mut buf)?;
-            if bytes_read == 0 {
-                break;
+/// Skips the continuation bytes of a character that starts before `file`,
+/// at most the three a UTF-8 character can have
+fn align_to_char(file: &mut impl BufRead) -> io::Result<()> {
+    for _ in 0..3 {
+        match file.fill_buf()?.first() {
+            Some(byte) if byte & 0b1100_0000 == 0b1000_0000 => file.consume(1),
+            _ => break,
+        }
+    }
+    Ok(())
+}
+/// Prints from line `+N` reading forward, or the last `N` lines kept in a
+/// ring buffer while reading the whole stream
+fn print_lines<T>(mut file: T, num_lines: &TakeValue) -> TailResult<()>
+where
+    T: BufRead,
+{
+    let mut buf = Vec::new();
+    match num_lines {
+        PlusZero => {}
+        TakeNum(0) => return Ok(()),
+        TakeNum(take) if *take < 0 => {
+            for line in last_lines(file, take.unsigned_abs())? {
+                print!("{}", String::from_utf8_lossy(&line));
             }
-            if line_num >= start {
-                print!("{}", String::from_utf8_lossy(&buf));
+            return Ok(());
+        }
+        TakeNum(take) => {
+            for _ in 1..*take {
+                if file.read_until(b'\n', &mut buf)? == 0 {
+                    return Ok(());
+                }
+                buf.clear();
             }
-            line_num += 1;
-            buf.clear();
         }
     }
+    while file.read_until(b'\n', &mut buf)? > 0 {
+        print!("{}", String::from_utf8_lossy(&buf));
+        buf.clear();
+    }
     Ok(())
 }
+/// Reads `file` to the end, keeping only the last `num_bytes` bytes
+fn last_bytes(mut file: impl Read, num_bytes: u64) -> io::Result<Vec<u8>> {
+    let num_bytes = usize::try_from(num_bytes).unwrap_or(usize::MAX);
+    let mut ring = VecDeque::new();
+    let mut buffer = vec![0; BLOCK_SIZE];
+    loop {
+        match file.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(n) => ring.extend(&buffer[..n]),
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        }
+        let excess = ring.len().saturating_sub(num_bytes);
+        ring.drain(..excess);
+    }
+    Ok(ring.into())
+}
+/// Reads `file` to the end, keeping only the last `num_lines` lines
+fn last_lines(mut file: impl BufRead, num_lines: u64) -> io::Result<VecDeque<Vec<u8>>> {
+    let mut ring = VecDeque::new();
+    let mut buf = Vec::new();
+    while file.read_until(b'\n', &mut buf)? > 0 {
+        ring.push_back(buf);
+        buf = Vec::new();
+        if ring.len() as u64 > num_lines {
+            // Reuse the allocation of the line that dropped out
+            buf = ring.pop_front().unwrap_or_default();
+            buf.clear();
+        }
+    }
+    Ok(ring)
+}
+/// Returns the offset where the last `num_lines` lines of `file` start,
+/// reading it backwards in blocks until enough newlines are found
+#[doc(hidden)]
+pub fn last_lines_start<T>(file: &mut T, num_lines: u64) -> io::Result<u64>
+where
+    T: Read + Seek,
+{
+    let len = file.seek(SeekFrom::End(0))?;
+    let mut buffer = vec![0; BLOCK_SIZE];
+    let mut newlines = 0;
+    let mut end = len;
+    while end > 0 {
+        let start = end.saturating_sub(BLOCK_SIZE as u64);
+        let block = &mut buffer[..(end - start) as usize];
+        file.seek(SeekFrom::Start(start))?;
+        file.read_exact(block)?;
+        for idx in (0..block.len()).rev() {
+            let line_start = start + idx as u64 + 1;
+            // The newline ending the file closes the last line, it doesn't start one
+            if block[idx] == b'\n' && line_start != len {
+                newlines += 1;
+                if newlines == num_lines {
+                    return Ok(line_start);
+                }
+            }
+        }
+        end = start;
+    }
+    Ok(0)
+}
 fn get_start_index(take_val: &TakeValue, total: i64) -> Option<u64> {
     match (take_val.clone(), total) {
         (_, 0) => None,
@@ -126,8 +477,12 @@ pub fn get_args() -> TailResult<Config> {
 }
 #[cfg(test)]
 mod tests {
-    use super::{count_lines_bytes, get_start_index, parse_num, TakeValue::*};
+    use super::{
+        align_to_char, get_start_index, last_bytes, last_lines, last_lines_start, parse_num,
+        TakeValue::*, BLOCK_SIZE,
+    };
     use pretty_assertions::assert_eq;
+    use std::io::Cursor;
     #[test]
     fn test_parse_num_positive() {
         // A leading "+" should result in a positive number
@@ -201,13 +556,76 @@ mod tests {
         assert_eq!(res.unwrap_err().to_string(), "foo");
     }
     #[test]
-    fn test_count_lines_bytes() {
-        let res = count_lines_bytes("tests/inputs/one.txt");
-        assert!(res.is_ok());
-        assert_eq!(res.unwrap(), (1, 24));
-        let res = count_lines_bytes("tests/inputs/ten.txt");
-        assert!(res.is_ok());
-        assert_eq!(res.unwrap(), (10, 49));
+    fn test_last_lines_start() {
+        let start = |text: &str, num_lines| last_lines_start(&mut Cursor::new(text), num_lines);
+        assert_eq!(start("", 1).unwrap(), 0);
+        assert_eq!(start("a\nb\nc\n", 1).unwrap(), 4);
+        assert_eq!(start("a\nb\nc\n", 2).unwrap(), 2);
+        assert_eq!(start("a\nb\nc\n", 3).unwrap(), 0);
+        assert_eq!(start("a\nb\nc\n", 4).unwrap(), 0);
+        // A last line without a newline still counts
+        assert_eq!(start("a\nb\nc", 1).unwrap(), 4);
+        assert_eq!(start("a\nb\nc", 3).unwrap(), 0);
+        assert_eq!(start("\n\n", 1).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_last_bytes() {
+        assert_eq!(last_bytes("".as_bytes(), 3).unwrap(), b"");
+        assert_eq!(last_bytes("abcdef".as_bytes(), 3).unwrap(), b"def");
+        assert_eq!(last_bytes("abcdef".as_bytes(), 10).unwrap(), b"abcdef");
+        let long = "x".repeat(BLOCK_SIZE * 2) + "end";
+        assert_eq!(last_bytes(long.as_bytes(), 4).unwrap(), b"xend");
+        assert_eq!(
+            last_bytes(long.as_bytes(), u64::MAX).unwrap().len(),
+            long.len()
+        );
+    }
+
+    #[test]
+    fn test_align_to_char() {
+        let aligned = |bytes: &[u8]| {
+            let mut file = bytes;
+            align_to_char(&mut file).unwrap();
+            file.to_vec()
+        };
+        assert_eq!(aligned(b""), b"");
+        assert_eq!(aligned(b"abc"), b"abc");
+        assert_eq!(aligned("ábc".as_bytes()), "ábc".as_bytes());
+        assert_eq!(aligned(&"ábc".as_bytes()[1..]), b"bc");
+        assert_eq!(aligned(&"😀!".as_bytes()[1..]), b"!");
+        // Invalid UTF-8 is only skipped as far as a character could reach
+        assert_eq!(aligned(&[0x80, 0x80, 0x80, 0x80, b'a']), [0x80, b'a']);
+    }
+
+    #[test]
+    fn test_last_lines() {
+        assert!(last_lines("".as_bytes(), 3).unwrap().is_empty());
+        assert_eq!(
+            last_lines("a\nb\nc\n".as_bytes(), 2).unwrap(),
+            [b"b\n", b"c\n"]
+        );
+        assert_eq!(
+            last_lines("a\nb\nc".as_bytes(), 2).unwrap(),
+            [&b"b\n"[..], b"c"]
+        );
+        assert_eq!(
+            last_lines("a\nb\n".as_bytes(), 5).unwrap(),
+            [b"a\n", b"b\n"]
+        );
+    }
+
+    #[test]
+    fn test_last_lines_start_across_blocks() {
+        let long = format!("{}\nend\n", "x".repeat(BLOCK_SIZE * 2));
+        assert_eq!(
+            last_lines_start(&mut Cursor::new(&long), 1).unwrap(),
+            BLOCK_SIZE as u64 * 2 + 1
+        );
+        assert_eq!(last_lines_start(&mut Cursor::new(&long), 2).unwrap(), 0);
+        let many = "line\n".repeat(BLOCK_SIZE);
+        let res = last_lines_start(&mut Cursor::new(&many), 20_000);
+        assert_eq!(res.unwrap(), (BLOCK_SIZE as u64 - 20_000) * 5);
     }
     #[test]
     fn test_get_start_index_empty_file() {
@@ -1,8 +1,15 @@
+#![allow(clippy::needless_borrows_for_generic_args)]
+
 use assert_cmd::Command;
 use predicates::prelude::*;
 use rand::{distributions::Alphanumeric, Rng};
-use std::fs::{self, File};
-use std::io::Read;
+use std::fs::{self, OpenOptions};
+use std::io::{Read, Write};
+use std::path::Path;
+use std::process::{Child, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
 
 type TestResult = Result<(), Box<dyn std::error::Error>>;
 
@@ -32,17 +39,6 @@ fn gen_bad_file() -> String {
     }
 }
 
-// --------------------------------------------------
-#[test]
-fn dies_no_args() -> TestResult {
-    Command::cargo_bin(PRG)?
-        .assert()
-        .failure()
-        .stderr(predicate::str::contains("Usage"));
-
-    Ok(())
-}
-
 // --------------------------------------------------
 #[test]
 fn dies_bad_bytes() -> TestResult {
@@ -98,18 +94,29 @@ fn skips_bad_file() -> TestResult {
     Ok(())
 }
 
+// --------------------------------------------------
+#[test]
+fn reports_directory() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["tests/inputs", ONE])
+        .assert()
+        .stderr(predicate::str::is_match(
+            "tests/inputs: .* [(]os error 21[)]",
+        )?)
+        .stdout(predicate::str::contains("==> tests/inputs <=="));
+
+    Ok(())
+}
+
 // --------------------------------------------------
 fn run(args: &[&str], expected_file: &str) -> TestResult {
-    // Extra work here due to lossy UTF
-    let mut file = File::open(expected_file)?;
-    let mut buffer = Vec::new();
-    file.read_to_end(&mut buffer)?;
-    let expected = String::from_utf8_lossy(&buffer);
+    // Byte tails are compared as is, they may split a character
+    let expected = fs::read(expected_file)?;
 
     Command::cargo_bin(PRG)?
         .args(args)
         .assert()
-        .stdout(predicate::eq(expected.as_bytes() as &[u8]));
+        .stdout(predicate::eq(expected));
 
     Ok(())
 }
@@ -828,3 +835,355 @@ fn multiple_files_c_plus_3() -> TestResult {
         "tests/expected/all.c+3.out",
     )
 }
+
+// --------------------------------------------------
+/// Output collected from a `tailr` that keeps running in the background
+struct Follower {
+    child: Child,
+    output: Arc<Mutex<Vec<u8>>>,
+}
+impl Follower {
+    fn spawn(args: &[&str]) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut child = std::process::Command::new(assert_cmd::cargo::cargo_bin(PRG))
+            .args(args)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()?;
+        let mut stdout = child.stdout.take().unwrap();
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let collected = Arc::clone(&output);
+        thread::spawn(move || {
+            let mut buffer = [0; 1024];
+            while let Ok(n @ 1..) = stdout.read(&mut buffer) {
+                collected.lock().unwrap().extend_from_slice(&buffer[..n]);
+            }
+        });
+        Ok(Follower { child, output })
+    }
+    /// Waits a few seconds for the output to become `expected`
+    fn expect(&self, expected: &str) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while Instant::now() < deadline && *self.output.lock().unwrap() != expected.as_bytes() {
+            thread::sleep(Duration::from_millis(20));
+        }
+        let output = self.output.lock().unwrap();
+        assert_eq!(String::from_utf8_lossy(&output), expected);
+    }
+}
+impl Drop for Follower {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+// --------------------------------------------------
+fn append(path: &Path, text: &str) -> TestResult {
+    OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(path)?
+        .write_all(text.as_bytes())?;
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn follow_appended() -> TestResult {
+    let dir = tempfile::tempdir()?;
+    let log = dir.path().join("app.log");
+    fs::write(&log, "one\ntwo\n")?;
+    let tailr = Follower::spawn(&["-f", "-n", "1", log.to_str().unwrap()])?;
+    tailr.expect("two\n");
+    append(&log, "three\n")?;
+    tailr.expect("two\nthree\n");
+    append(&log, "fo")?;
+    append(&log, "ur\n")?;
+    tailr.expect("two\nthree\nfour\n");
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn follow_truncated() -> TestResult {
+    let dir = tempfile::tempdir()?;
+    let log = dir.path().join("app.log");
+    fs::write(&log, "one\ntwo\n")?;
+    let tailr = Follower::spawn(&["-f", "-c", "4", log.to_str().unwrap()])?;
+    tailr.expect("two\n");
+    fs::write(&log, "new\n")?;
+    tailr.expect("two\nnew\n");
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn follow_name_rotated() -> TestResult {
+    let dir = tempfile::tempdir()?;
+    let log = dir.path().join("app.log");
+    fs::write(&log, "one\n")?;
+    let tailr = Follower::spawn(&["-F", log.to_str().unwrap()])?;
+    tailr.expect("one\n");
+    append(&log, "two\n")?;
+    tailr.expect("one\ntwo\n");
+    fs::rename(&log, dir.path().join("app.log.1"))?;
+    append(&log, "three\n")?;
+    tailr.expect("one\ntwo\nthree\n");
+    append(&log, "four\n")?;
+    tailr.expect("one\ntwo\nthree\nfour\n");
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn follow_descriptor_ignores_rotation() -> TestResult {
+    let dir = tempfile::tempdir()?;
+    let log = dir.path().join("app.log");
+    let old = dir.path().join("app.log.1");
+    fs::write(&log, "one\n")?;
+    let tailr = Follower::spawn(&["-f", log.to_str().unwrap()])?;
+    tailr.expect("one\n");
+    fs::rename(&log, &old)?;
+    append(&log, "new\n")?;
+    append(&old, "two\n")?;
+    tailr.expect("one\ntwo\n");
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+#[cfg(unix)]
+fn follow_stops_with_pid() -> TestResult {
+    let dir = tempfile::tempdir()?;
+    let log = dir.path().join("app.log");
+    fs::write(&log, "one\n")?;
+    let mut writer = std::process::Command::new("sh")
+        .arg("-c")
+        .arg(format!("sleep 0.5; echo two >> {}", log.display()))
+        .spawn()?;
+    let pid = writer.id().to_string();
+    // Reaping the writer lets tailr see that it exited
+    thread::spawn(move || writer.wait());
+    Command::cargo_bin(PRG)?
+        .args(["-f", "--pid", &pid, log.to_str().unwrap()])
+        .timeout(Duration::from_secs(10))
+        .assert()
+        .success()
+        .stdout("one\ntwo\n");
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn reads_stdin_by_default() -> TestResult {
+    let input = fs::read_to_string(TEN)?;
+    let expected = fs::read_to_string("tests/expected/ten.txt.n3.out")?;
+    Command::cargo_bin(PRG)?
+        .args(["-n", "3"])
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout(expected);
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn reads_stdin_from_dash() -> TestResult {
+    let input = fs::read_to_string(TEN)?;
+    for (args, expected) in [
+        (["-c", "8"], "tests/expected/ten.txt.c8.out"),
+        (["-c", "+2"], "tests/expected/ten.txt.c+2.out"),
+        (["-n", "+2"], "tests/expected/ten.txt.n+2.out"),
+        (["-n", "200"], "tests/expected/ten.txt.n200.out"),
+    ] {
+        Command::cargo_bin(PRG)?
+            .args(args)
+            .arg("-")
+            .write_stdin(input.as_str())
+            .assert()
+            .success()
+            .stdout(fs::read_to_string(expected)?);
+    }
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn stdin_header() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-n", "1", ONE, "-"])
+        .write_stdin("last\n")
+        .assert()
+        .success()
+        .stdout(
+            "==> tests/inputs/one.txt <==\nÖne line, four wordś.\n\n==> standard input <==\nlast\n",
+        );
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+#[cfg(unix)]
+fn reads_fifo() -> TestResult {
+    let dir = tempfile::tempdir()?;
+    let fifo = dir.path().join("fifo");
+    assert!(std::process::Command::new("mkfifo")
+        .arg(&fifo)
+        .status()?
+        .success());
+    let writer = {
+        let fifo = fifo.clone();
+        thread::spawn(move || fs::write(fifo, fs::read(TEN)?))
+    };
+    Command::cargo_bin(PRG)?
+        .args(["-n", "4", fifo.to_str().unwrap()])
+        .timeout(Duration::from_secs(10))
+        .assert()
+        .success()
+        .stdout(fs::read_to_string("tests/expected/ten.txt.n4.out")?);
+    writer.join().unwrap()?;
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn bytes_pass_binary_through() -> TestResult {
+    let dir = tempfile::tempdir()?;
+    let binary = dir.path().join("binary");
+    let data: Vec<u8> = (0..=255).cycle().take(1000).collect();
+    fs::write(&binary, &data)?;
+    Command::cargo_bin(PRG)?
+        .args(["-c", "300", binary.to_str().unwrap()])
+        .assert()
+        .success()
+        .stdout(data[700..].to_vec());
+    Command::cargo_bin(PRG)?
+        .args(["-c", "300"])
+        .write_stdin(data.clone())
+        .assert()
+        .success()
+        .stdout(data[700..].to_vec());
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn align_chars() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-c", "3", "--align-chars", ONE])
+        .assert()
+        .success()
+        .stdout(".\n");
+    Command::cargo_bin(PRG)?
+        .args(["-c", "+2", "--align-chars", ONE])
+        .assert()
+        .success()
+        .stdout("ne line, four wordś.\n");
+    Command::cargo_bin(PRG)?
+        .args(["-c", "3", "--align-chars"])
+        .write_stdin("ábc")
+        .assert()
+        .success()
+        .stdout("bc");
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn dies_align_chars_without_bytes() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["--align-chars", ONE])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("--bytes <BYTES>"));
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn verbose_single_file() -> TestResult {
+    Command::cargo_bin(PRG)?
+        .args(["-v", "-n", "1", ONE])
+        .assert()
+        .success()
+        .stdout("==> tests/inputs/one.txt <==\nÖne line, four wordś.\n");
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn follow_headers_on_switch() -> TestResult {
+    let dir = tempfile::tempdir()?;
+    let (a, b) = (dir.path().join("a.log"), dir.path().join("b.log"));
+    fs::write(&a, "a1\n")?;
+    fs::write(&b, "b1\n")?;
+    let (a_name, b_name) = (a.to_str().unwrap(), b.to_str().unwrap());
+    let tailr = Follower::spawn(&["-f", a_name, b_name])?;
+    let mut expected = format!("==> {a_name} <==\na1\n\n==> {b_name} <==\nb1\n");
+    tailr.expect(&expected);
+    append(&b, "b2\n")?;
+    expected += "b2\n";
+    tailr.expect(&expected);
+    append(&a, "a2\n")?;
+    expected += &format!("\n==> {a_name} <==\na2\n");
+    tailr.expect(&expected);
+    append(&b, "b3\n")?;
+    expected += &format!("\n==> {b_name} <==\nb3\n");
+    tailr.expect(&expected);
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn follow_quiet_and_verbose() -> TestResult {
+    let dir = tempfile::tempdir()?;
+    let (a, b) = (dir.path().join("a.log"), dir.path().join("b.log"));
+    fs::write(&a, "a1\n")?;
+    fs::write(&b, "b1\n")?;
+    let (a_name, b_name) = (a.to_str().unwrap(), b.to_str().unwrap());
+    let quiet = Follower::spawn(&["-f", "-q", a_name, b_name])?;
+    let verbose = Follower::spawn(&["-f", "-v", a_name])?;
+    quiet.expect("a1\nb1\n");
+    verbose.expect(&format!("==> {a_name} <==\na1\n"));
+    append(&a, "a2\n")?;
+    append(&b, "b2\n")?;
+    quiet.expect("a1\nb1\na2\nb2\n");
+    verbose.expect(&format!("==> {a_name} <==\na1\na2\n"));
+
+    Ok(())
+}
+
+// --------------------------------------------------
+#[test]
+fn follow_missing_until_it_appears() -> TestResult {
+    let dir = tempfile::tempdir()?;
+    let (a, b) = (dir.path().join("a.log"), dir.path().join("b.log"));
+    fs::write(&a, "a1\n")?;
+    let (a_name, b_name) = (a.to_str().unwrap(), b.to_str().unwrap());
+    let tailr = Follower::spawn(&["-f", a_name, b_name])?;
+    let mut expected = format!("==> {a_name} <==\na1\n");
+    tailr.expect(&expected);
+    fs::write(&b, "b1\nb2\n")?;
+    expected += &format!("\n==> {b_name} <==\nb1\nb2\n");
+    tailr.expect(&expected);
+    append(&b, "b3\n")?;
+    expected += "b3\n";
+    tailr.expect(&expected);
+
+    Ok(())
+}